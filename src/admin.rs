@@ -0,0 +1,193 @@
+//! Admin HTTP API: Prometheus metrics and a health check.
+//!
+//! `monitor` runs in the background with no way for an operator to see into
+//! it; this gives them a `/metrics` scrape target (DLQ depth, replay
+//! counts, per-destination write latency, pipeline liveness) plus a
+//! `/health` endpoint for uptime checks.
+
+use crate::dlq::DlqStore;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use prometheus::{Encoder, HistogramVec, IntGauge, IntGaugeVec, Registry, TextEncoder};
+use sqlx::{Pool, Postgres};
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::info;
+
+/// Process-wide metrics registry, written to from wherever a write/replay
+/// happens and read from the `/metrics` handler.
+pub struct AdminMetrics {
+    registry: Registry,
+    dlq_depth: IntGaugeVec,
+    events_replayed_total: IntGauge,
+    events_failed_total: IntGauge,
+    write_latency_seconds: HistogramVec,
+    pipeline_alive: IntGauge,
+}
+
+impl AdminMetrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+
+        let dlq_depth = IntGaugeVec::new(
+            prometheus::Opts::new("dlq_depth", "Pending DLQ batches per destination"),
+            &["pipeline_dest_id"],
+        )
+        .expect("valid dlq_depth metric");
+
+        let events_replayed_total = IntGauge::new(
+            "dlq_events_replayed_total",
+            "Total event batches successfully replayed from the DLQ",
+        )
+        .expect("valid events_replayed_total metric");
+
+        let events_failed_total = IntGauge::new(
+            "dlq_events_failed_total",
+            "Total event batches that failed replay and were re-queued",
+        )
+        .expect("valid events_failed_total metric");
+
+        let write_latency_seconds = HistogramVec::new(
+            prometheus::HistogramOpts::new(
+                "destination_write_latency_seconds",
+                "Latency of writes to a destination",
+            ),
+            &["destination"],
+        )
+        .expect("valid write_latency_seconds metric");
+
+        let pipeline_alive = IntGauge::new("pipeline_alive", "1 if the pipeline manager is running")
+            .expect("valid pipeline_alive metric");
+
+        registry
+            .register(Box::new(dlq_depth.clone()))
+            .expect("register dlq_depth");
+        registry
+            .register(Box::new(events_replayed_total.clone()))
+            .expect("register events_replayed_total");
+        registry
+            .register(Box::new(events_failed_total.clone()))
+            .expect("register events_failed_total");
+        registry
+            .register(Box::new(write_latency_seconds.clone()))
+            .expect("register write_latency_seconds");
+        registry
+            .register(Box::new(pipeline_alive.clone()))
+            .expect("register pipeline_alive");
+
+        Self {
+            registry,
+            dlq_depth,
+            events_replayed_total,
+            events_failed_total,
+            write_latency_seconds,
+            pipeline_alive,
+        }
+    }
+
+    pub fn observe_write_latency(&self, destination: &str, latency: Duration) {
+        self.write_latency_seconds
+            .with_label_values(&[destination])
+            .observe(latency.as_secs_f64());
+    }
+
+    pub fn inc_replayed(&self) {
+        self.events_replayed_total.inc();
+    }
+
+    pub fn inc_failed(&self) {
+        self.events_failed_total.inc();
+    }
+
+    pub fn set_pipeline_alive(&self, alive: bool) {
+        self.pipeline_alive.set(alive as i64);
+    }
+
+    async fn refresh_dlq_depth(&self, dlq: &DlqStore) {
+        self.dlq_depth.reset();
+        for dest_id in dlq.pending_destinations().await {
+            let depth = dlq.count_for_destination(dest_id).await;
+            self.dlq_depth
+                .with_label_values(&[&dest_id.to_string()])
+                .set(depth as i64);
+        }
+    }
+}
+
+static METRICS: OnceLock<AdminMetrics> = OnceLock::new();
+
+/// Global metrics handle. Lazily initialized on first use so any module can
+/// record a measurement without threading a handle through every call site.
+pub fn metrics() -> &'static AdminMetrics {
+    METRICS.get_or_init(AdminMetrics::new)
+}
+
+#[derive(Clone)]
+struct AdminState {
+    dlq: DlqStore,
+    pg_pool: Pool<Postgres>,
+}
+
+async fn metrics_handler(State(state): State<AdminState>) -> impl IntoResponse {
+    metrics().refresh_dlq_depth(&state.dlq).await;
+
+    let encoder = TextEncoder::new();
+    let metric_families = metrics().registry.gather();
+    let mut buffer = Vec::new();
+    if let Err(err) = encoder.encode(&metric_families, &mut buffer) {
+        tracing::error!("Failed to encode Prometheus metrics: {}", err);
+        return (StatusCode::INTERNAL_SERVER_ERROR, String::new());
+    }
+
+    (
+        StatusCode::OK,
+        String::from_utf8(buffer).unwrap_or_default(),
+    )
+}
+
+async fn health_handler(State(state): State<AdminState>) -> impl IntoResponse {
+    match sqlx::query("SELECT 1").execute(&state.pg_pool).await {
+        Ok(_) => StatusCode::OK,
+        Err(err) => {
+            tracing::warn!("Health check failed, config DB unreachable: {}", err);
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    }
+}
+
+/// Starts the admin HTTP server in the background, bound to `addr`.
+pub fn start(addr: SocketAddr, pg_pool: Pool<Postgres>, dlq: DlqStore) {
+    let state = AdminState { dlq, pg_pool };
+
+    tokio::spawn(async move {
+        let app = Router::new()
+            .route("/metrics", get(metrics_handler))
+            .route("/health", get(health_handler))
+            .with_state(state);
+
+        info!("Admin HTTP API listening on {}", addr);
+        match tokio::net::TcpListener::bind(addr).await {
+            Ok(listener) => {
+                if let Err(err) = axum::serve(listener, app).await {
+                    tracing::error!("Admin HTTP API server error: {}", err);
+                }
+            }
+            Err(err) => {
+                tracing::error!("Failed to bind admin HTTP API on {}: {}", addr, err);
+            }
+        }
+    });
+}
+
+/// Returns the configured admin bind address, defaulting to `127.0.0.1:9090`.
+pub fn configured_addr() -> SocketAddr {
+    std::env::var("ADMIN_HTTP_ADDR")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 9090)))
+}
+
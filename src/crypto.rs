@@ -1,69 +1,292 @@
 use aes_gcm::{
-    aead::{Aead, KeyInit},
+    aead::{Aead, AeadCore, KeyInit, OsRng},
     Aes256Gcm, Nonce,
 };
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use base64::{engine::general_purpose, Engine as _};
+use std::collections::HashMap;
 use std::env;
 
-/// Decrypts a base64 encoded string using AES-256-GCM.
-/// 
-/// Expectations:
-/// - Environment variable `CREDENTIAL_ENCRYPTION_KEY` must be set (32 bytes).
-/// - Input `encrypted_value` is base64 encoded string containing `Nonce (12 bytes) + Ciphertext + Tag (16 bytes)`.
-pub fn decrypt_value(encrypted_value: &str) -> Result<String> {
-    if encrypted_value.is_empty() {
-        return Ok(encrypted_value.to_string());
+/// A stored ciphertext is either the legacy id-less single-key format
+/// (just base64(nonce + ciphertext + tag)) or `"<key_id>:" + base64(...)`.
+/// Base64's alphabet never contains `:`, so splitting on the first one is
+/// unambiguous.
+const KEY_ID_SEPARATOR: char = ':';
+
+/// A set of AES-256-GCM keys, indexed by id, with one marked active for new
+/// encryptions. Parsed from `CREDENTIAL_ENCRYPTION_KEYS`, a JSON object:
+/// `{"active": "v2", "keys": {"v1": "<base64>", "v2": "<base64>"}}`.
+struct Keyring {
+    active_id: String,
+    keys: HashMap<String, Vec<u8>>,
+}
+
+#[derive(serde::Deserialize)]
+struct KeyringConfig {
+    active: String,
+    keys: HashMap<String, String>,
+}
+
+impl Keyring {
+    fn from_env() -> Result<Option<Self>> {
+        let Ok(raw) = env::var("CREDENTIAL_ENCRYPTION_KEYS") else {
+            return Ok(None);
+        };
+
+        let config: KeyringConfig =
+            serde_json::from_str(&raw).context("CREDENTIAL_ENCRYPTION_KEYS must be valid JSON")?;
+
+        if !config.keys.contains_key(&config.active) {
+            return Err(anyhow!(
+                "CREDENTIAL_ENCRYPTION_KEYS active id '{}' has no matching key",
+                config.active
+            ));
+        }
+
+        let keys = config
+            .keys
+            .into_iter()
+            .map(|(id, key_str)| Ok((id, decode_key(&key_str)?)))
+            .collect::<Result<HashMap<_, _>>>()?;
+
+        Ok(Some(Self {
+            active_id: config.active,
+            keys,
+        }))
     }
 
-    let key_str = env::var("CREDENTIAL_ENCRYPTION_KEY")
-        .map_err(|_| anyhow!("CREDENTIAL_ENCRYPTION_KEY must be set"))?;
+    fn cipher_for(&self, key_id: &str) -> Result<Aes256Gcm> {
+        let key_bytes = self
+            .keys
+            .get(key_id)
+            .ok_or_else(|| anyhow!("No encryption key registered for id '{}'", key_id))?;
+        Aes256Gcm::new_from_slice(key_bytes).map_err(|e| anyhow!("Failed to create cipher for key '{}': {}", key_id, e))
+    }
 
-    // Prepare key
-    let key_bytes = if let Ok(decoded) = general_purpose::STANDARD.decode(&key_str) {
+    fn active_cipher(&self) -> Result<Aes256Gcm> {
+        self.cipher_for(&self.active_id)
+    }
+}
+
+/// Accepts either a raw 32-byte string or a base64-encoded 32-byte string,
+/// matching the legacy `CREDENTIAL_ENCRYPTION_KEY` convention.
+fn decode_key(key_str: &str) -> Result<Vec<u8>> {
+    if let Ok(decoded) = general_purpose::STANDARD.decode(key_str) {
         if decoded.len() == 32 {
-            decoded
-        } else {
-            // If decode success but length wrong, maybe it was a raw string that coincidentally is valid base64?
-            // Safer to assume if it decodes to 32 bytes it is the key.
-            // If not, fallback to raw bytes logic or error.
-            // Python side: "if len(decoded) == 32: return AESGCM(decoded)"
-            // "if len(key.encode()) == 32: return AESGCM(key.encode())"
-            
-            if key_str.len() == 32 {
-                key_str.as_bytes().to_vec()
-            } else {
-                 return Err(anyhow!("Invalid key length. Must be 32 bytes (raw) or base64 encoded 32 bytes."));
-            }
+            return Ok(decoded);
         }
-    } else {
-        if key_str.len() == 32 {
-            key_str.as_bytes().to_vec()
-        } else {
-            return Err(anyhow!("Invalid key length. Must be 32 bytes."));
-        }
-    };
+    }
+
+    if key_str.len() == 32 {
+        return Ok(key_str.as_bytes().to_vec());
+    }
+
+    Err(anyhow!(
+        "Invalid key length. Must be 32 bytes (raw) or base64 encoded 32 bytes."
+    ))
+}
 
-    let cipher = Aes256Gcm::new_from_slice(&key_bytes)
-        .map_err(|e| anyhow!("Failed to create cipher: {}", e))?;
+fn legacy_cipher() -> Result<Aes256Gcm> {
+    let key_str = env::var("CREDENTIAL_ENCRYPTION_KEY")
+        .map_err(|_| anyhow!("CREDENTIAL_ENCRYPTION_KEY must be set"))?;
+    let key_bytes = decode_key(&key_str)?;
+    Aes256Gcm::new_from_slice(&key_bytes).map_err(|e| anyhow!("Failed to create cipher: {}", e))
+}
 
-    // Decode base64 input
+fn open_with_cipher(cipher: &Aes256Gcm, payload_b64: &str) -> Result<String> {
     let combined = general_purpose::STANDARD
-        .decode(encrypted_value)
+        .decode(payload_b64)
         .map_err(|e| anyhow!("Failed to decode base64 value: {}", e))?;
 
     if combined.len() < 12 {
         return Err(anyhow!("Encrypted value too short"));
     }
 
-    // Extract Nonce (first 12 bytes)
     let (nonce_bytes, ciphertext) = combined.split_at(12);
     let nonce = Nonce::from_slice(nonce_bytes);
 
-    // Decrypt
     let plaintext = cipher
         .decrypt(nonce, ciphertext)
         .map_err(|e| anyhow!("Decryption failed: {}", e))?;
 
     Ok(String::from_utf8(plaintext)?)
 }
+
+/// Decrypts a stored credential, transparently picking the right key by its
+/// embedded key id.
+///
+/// Expectations:
+/// - `encrypted_value` is either `"<key_id>:" + base64(nonce + ciphertext + tag)`
+///   (current format, resolved against `CREDENTIAL_ENCRYPTION_KEYS`), or a
+///   bare `base64(nonce + ciphertext + tag)` with no id prefix (legacy
+///   format, resolved against the single `CREDENTIAL_ENCRYPTION_KEY`).
+/// - This lets credentials encrypted under an old key keep decrypting
+///   during a rotation, without a flag-day migration.
+pub fn decrypt_value(encrypted_value: &str) -> Result<String> {
+    if encrypted_value.is_empty() {
+        return Ok(encrypted_value.to_string());
+    }
+
+    if let Some((key_id, payload_b64)) = encrypted_value.split_once(KEY_ID_SEPARATOR) {
+        if let Some(keyring) = Keyring::from_env()? {
+            let cipher = keyring.cipher_for(key_id)?;
+            return open_with_cipher(&cipher, payload_b64);
+        }
+        // No keyring configured but the payload carries an id - fall through
+        // to the legacy path is not meaningful here since the id can't be
+        // resolved, so surface a clear error instead of silently misreading
+        // the id as part of the ciphertext.
+        return Err(anyhow!(
+            "Value is encrypted with key id '{}' but CREDENTIAL_ENCRYPTION_KEYS is not set",
+            key_id
+        ));
+    }
+
+    // No id prefix: legacy single-key format.
+    let cipher = legacy_cipher()?;
+    open_with_cipher(&cipher, encrypted_value)
+}
+
+/// Encrypts `plaintext` under the keyring's active key, prepending that
+/// key's id so a later rotation can still find it.
+///
+/// Requires `CREDENTIAL_ENCRYPTION_KEYS` to be set; there is no "legacy
+/// encrypt" path since new ciphertexts should always be versioned.
+pub fn encrypt_value(plaintext: &str) -> Result<String> {
+    if plaintext.is_empty() {
+        return Ok(plaintext.to_string());
+    }
+
+    let keyring = Keyring::from_env()?
+        .ok_or_else(|| anyhow!("CREDENTIAL_ENCRYPTION_KEYS must be set to encrypt values"))?;
+    let cipher = keyring.active_cipher()?;
+
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext.as_bytes())
+        .map_err(|e| anyhow!("Encryption failed: {}", e))?;
+
+    let mut combined = nonce.to_vec();
+    combined.extend_from_slice(&ciphertext);
+    let payload_b64 = general_purpose::STANDARD.encode(combined);
+
+    Ok(format!("{}{}{}", keyring.active_id, KEY_ID_SEPARATOR, payload_b64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// `encrypt_value`/`decrypt_value` read process-global env vars, so
+    /// tests that set them have to run one at a time - cargo runs tests in
+    /// parallel threads within one process by default, and two tests racing
+    /// to set/unset `CREDENTIAL_ENCRYPTION_KEYS` would flake each other.
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    fn with_env<R>(vars: &[(&str, Option<&str>)], f: impl FnOnce() -> R) -> R {
+        let _guard = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        for (key, value) in vars {
+            match value {
+                Some(v) => env::set_var(key, v),
+                None => env::remove_var(key),
+            }
+        }
+        let result = f();
+        for (key, _) in vars {
+            env::remove_var(key);
+        }
+        result
+    }
+
+    const KEY_V1: &str = "v1-key-that-is-exactly-32-bytes!";
+    const KEY_V2: &str = "v2-key-that-is-exactly-32-bytes!";
+
+    fn keyring_json(active: &str) -> String {
+        format!(
+            r#"{{"active": "{active}", "keys": {{"v1": "{KEY_V1}", "v2": "{KEY_V2}"}}}}"#
+        )
+    }
+
+    #[test]
+    fn decode_key_accepts_raw_32_byte_string() {
+        assert_eq!(decode_key(KEY_V1).unwrap(), KEY_V1.as_bytes());
+    }
+
+    #[test]
+    fn decode_key_accepts_base64_encoded_32_bytes() {
+        let encoded = general_purpose::STANDARD.encode(KEY_V1);
+        assert_eq!(decode_key(&encoded).unwrap(), KEY_V1.as_bytes());
+    }
+
+    #[test]
+    fn decode_key_rejects_wrong_length() {
+        assert!(decode_key("too-short").is_err());
+    }
+
+    #[test]
+    fn empty_string_passes_through_both_directions() {
+        assert_eq!(encrypt_value("").unwrap(), "");
+        assert_eq!(decrypt_value("").unwrap(), "");
+    }
+
+    #[test]
+    fn encrypt_then_decrypt_round_trips_under_the_active_key() {
+        with_env(&[("CREDENTIAL_ENCRYPTION_KEYS", Some(&keyring_json("v2")))], || {
+            let encrypted = encrypt_value("super-secret").unwrap();
+            assert!(encrypted.starts_with("v2:"));
+            assert_eq!(decrypt_value(&encrypted).unwrap(), "super-secret");
+        });
+    }
+
+    #[test]
+    fn rotating_the_active_key_still_decrypts_ciphertext_from_the_old_one() {
+        let encrypted = with_env(&[("CREDENTIAL_ENCRYPTION_KEYS", Some(&keyring_json("v1")))], || {
+            encrypt_value("pre-rotation-secret").unwrap()
+        });
+        assert!(encrypted.starts_with("v1:"));
+
+        // Rotate: v2 becomes active, but v1's key is still registered so
+        // anything encrypted before the rotation keeps decrypting.
+        with_env(&[("CREDENTIAL_ENCRYPTION_KEYS", Some(&keyring_json("v2")))], || {
+            assert_eq!(decrypt_value(&encrypted).unwrap(), "pre-rotation-secret");
+        });
+    }
+
+    #[test]
+    fn legacy_unprefixed_ciphertext_decrypts_via_credential_encryption_key() {
+        with_env(&[("CREDENTIAL_ENCRYPTION_KEY", Some(KEY_V1)), ("CREDENTIAL_ENCRYPTION_KEYS", None)], || {
+            let cipher = legacy_cipher().unwrap();
+            let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+            let ciphertext = cipher.encrypt(&nonce, b"legacy-secret".as_slice()).unwrap();
+            let mut combined = nonce.to_vec();
+            combined.extend_from_slice(&ciphertext);
+            let payload_b64 = general_purpose::STANDARD.encode(combined);
+
+            assert_eq!(decrypt_value(&payload_b64).unwrap(), "legacy-secret");
+        });
+    }
+
+    #[test]
+    fn unknown_key_id_in_ciphertext_errors() {
+        with_env(&[("CREDENTIAL_ENCRYPTION_KEYS", Some(&keyring_json("v1")))], || {
+            let err = decrypt_value("v99:not-a-real-payload").unwrap_err();
+            assert!(err.to_string().contains("v99"));
+        });
+    }
+
+    #[test]
+    fn prefixed_ciphertext_without_a_keyring_configured_errors_instead_of_misreading_the_id() {
+        with_env(&[("CREDENTIAL_ENCRYPTION_KEYS", None)], || {
+            let err = decrypt_value("v1:whatever").unwrap_err();
+            assert!(err.to_string().contains("CREDENTIAL_ENCRYPTION_KEYS"));
+        });
+    }
+
+    #[test]
+    fn encrypt_value_without_a_keyring_configured_errors() {
+        with_env(&[("CREDENTIAL_ENCRYPTION_KEYS", None)], || {
+            assert!(encrypt_value("anything").is_err());
+        });
+    }
+}
@@ -2,15 +2,90 @@ use etl::destination::Destination;
 use etl::error::EtlResult;
 use etl::types::{Event, TableId, TableRow};
 use std::sync::Arc;
+use tracing::warn;
 
+use crate::dlq::DlqStore;
 use crate::postgres::destination::PostgresDuckdbDestination;
+use crate::retry::{with_retry, RetryConfig};
 use crate::snowflake::SnowflakeDestination;
 
 #[derive(Clone)]
 pub enum DestinationEnum {
-    Snowflake(SnowflakeDestination),
-    Postgres(PostgresDuckdbDestination),
-    Multi(Arc<Vec<Box<DestinationEnum>>>),
+    Snowflake {
+        dest_id: i32,
+        inner: SnowflakeDestination,
+    },
+    Postgres {
+        dest_id: i32,
+        inner: PostgresDuckdbDestination,
+    },
+    Multi {
+        dests: Arc<Vec<Box<DestinationEnum>>>,
+        /// Where a failing child's events are parked, keyed by that
+        /// child's own `pipeline_dest_id` so a targeted replay later only
+        /// touches the sink that actually failed.
+        dlq: DlqStore,
+    },
+}
+
+impl DestinationEnum {
+    /// The stable id of the pipeline destination this sink writes to.
+    /// `Multi` has no single id of its own; it only routes to its children.
+    pub fn pipeline_dest_id(&self) -> Option<i32> {
+        match self {
+            DestinationEnum::Snowflake { dest_id, .. } => Some(*dest_id),
+            DestinationEnum::Postgres { dest_id, .. } => Some(*dest_id),
+            DestinationEnum::Multi { .. } => None,
+        }
+    }
+
+    /// Drains every Snowflake leg's per-table micro-batching buffers so
+    /// nothing sitting in an in-memory `mpsc` channel is lost. Call this
+    /// from the process's shutdown path before exiting - `enqueue` only
+    /// promises the rows are buffered, not that they've reached Snowflake.
+    pub async fn flush(&self) {
+        match self {
+            DestinationEnum::Snowflake { inner, .. } => inner.flush().await,
+            DestinationEnum::Postgres { .. } => {}
+            DestinationEnum::Multi { dests, .. } => {
+                for dest in dests.iter() {
+                    dest.flush().await;
+                }
+            }
+        }
+    }
+}
+
+/// Groups events by table id so they can be DLQ'd per `(dest_id, table)`,
+/// matching the keying `DlqStore` already uses. Events with no table of
+/// their own (`Relation`/`Begin`/`Commit`) can't be slotted into that
+/// per-table keying, so they aren't queued; they're logged instead of
+/// silently dropped so a replay that's missing relation/schema context is
+/// at least visible.
+fn group_events_by_table(events: Vec<Event>) -> Vec<(TableId, Vec<Event>)> {
+    let mut grouped: Vec<(TableId, Vec<Event>)> = Vec::new();
+    for event in events {
+        let table_id = match &event {
+            Event::Insert(i) => i.table_id,
+            Event::Update(u) => u.table_id,
+            Event::Delete(d) => d.table_id,
+            Event::Truncate(t) => t.table_id,
+            other => {
+                warn!(event = ?other, "Dropping non-DML event while queuing a failed batch to DLQ; it has no table to key on");
+                continue;
+            }
+        };
+
+        match grouped.iter_mut().find(|(tid, _)| *tid == table_id) {
+            Some((_, evs)) => evs.push(event),
+            None => grouped.push((table_id, vec![event])),
+        }
+    }
+    grouped
+}
+
+fn dlq_table_key(table_id: TableId) -> String {
+    format!("table_{}", table_id.0)
 }
 
 #[allow(refining_impl_trait)]
@@ -24,20 +99,36 @@ impl Destination for DestinationEnum {
         table_id: TableId,
     ) -> std::pin::Pin<Box<dyn std::future::Future<Output = EtlResult<()>> + Send + '_>> {
         Box::pin(async move {
+            let retry_config = RetryConfig::default();
             match self {
-                DestinationEnum::Snowflake(d) => d.truncate_table(table_id).await,
-                DestinationEnum::Postgres(d) => d.truncate_table(table_id).await,
-                DestinationEnum::Multi(dests) => {
+                DestinationEnum::Snowflake { inner, .. } => {
+                    with_retry(&retry_config, || inner.truncate_table(table_id)).await
+                }
+                DestinationEnum::Postgres { inner, .. } => {
+                    with_retry(&retry_config, || inner.truncate_table(table_id)).await
+                }
+                DestinationEnum::Multi { dests, dlq: _ } => {
                     let mut handles = vec![];
                     for dest in dests.iter() {
                         let dest = dest.clone();
                         let tid = table_id.clone();
-                        handles.push(tokio::spawn(async move { dest.truncate_table(tid).await }));
+                        let retry_config = retry_config.clone();
+                        handles.push(tokio::spawn(async move {
+                            let result = with_retry(&retry_config, || dest.truncate_table(tid)).await;
+                            (dest.pipeline_dest_id(), result)
+                        }));
                     }
 
+                    // Unlike write_events/write_table_rows, a failed truncate
+                    // has nowhere to go: there's no DLQ entry or replay path
+                    // that can "re-truncate" a table later, so a dropped
+                    // failure here means a destination silently diverges from
+                    // the others forever. Propagate the first error instead
+                    // of returning Ok(()) the way the other two methods do.
+                    let mut first_err = None;
                     for h in handles {
-                        match h.await {
-                            Ok(res) => res?,
+                        let (dest_id, result) = match h.await {
+                            Ok(pair) => pair,
                             Err(e) => {
                                 return Err((
                                     etl::error::ErrorKind::Unknown,
@@ -46,9 +137,19 @@ impl Destination for DestinationEnum {
                                 )
                                     .into());
                             }
+                        };
+                        if let Err(err) = result {
+                            warn!(pipeline_dest_id = ?dest_id, %err, "Destination failed to truncate table");
+                            if first_err.is_none() {
+                                first_err = Some(err);
+                            }
                         }
                     }
-                    Ok(())
+
+                    match first_err {
+                        Some(err) => Err(err),
+                        None => Ok(()),
+                    }
                 }
             }
         })
@@ -60,23 +161,40 @@ impl Destination for DestinationEnum {
         rows: Vec<TableRow>,
     ) -> std::pin::Pin<Box<dyn std::future::Future<Output = EtlResult<()>> + Send + '_>> {
         Box::pin(async move {
+            let retry_config = RetryConfig::default();
             match self {
-                DestinationEnum::Snowflake(d) => d.write_table_rows(table_id, rows).await,
-                DestinationEnum::Postgres(d) => d.write_table_rows(table_id, rows).await,
-                DestinationEnum::Multi(dests) => {
+                DestinationEnum::Snowflake { inner, .. } => {
+                    let start = std::time::Instant::now();
+                    let result =
+                        with_retry(&retry_config, || inner.write_table_rows(table_id, rows.clone())).await;
+                    crate::admin::metrics().observe_write_latency("snowflake", start.elapsed());
+                    result
+                }
+                DestinationEnum::Postgres { inner, .. } => {
+                    let start = std::time::Instant::now();
+                    let result =
+                        with_retry(&retry_config, || inner.write_table_rows(table_id, rows.clone())).await;
+                    crate::admin::metrics().observe_write_latency("postgres", start.elapsed());
+                    result
+                }
+                DestinationEnum::Multi { dests, dlq } => {
                     let mut handles = vec![];
                     for dest in dests.iter() {
                         let dest = dest.clone();
                         let tid = table_id.clone();
                         let r = rows.clone();
-                        handles.push(tokio::spawn(
-                            async move { dest.write_table_rows(tid, r).await },
-                        ));
+                        let retry_config = retry_config.clone();
+                        handles.push(tokio::spawn(async move {
+                            let result =
+                                with_retry(&retry_config, || dest.write_table_rows(tid, r.clone())).await;
+                            (dest.pipeline_dest_id(), result)
+                        }));
                     }
 
+                    let mut degraded = Vec::new();
                     for h in handles {
-                        match h.await {
-                            Ok(res) => res?,
+                        let (dest_id, result) = match h.await {
+                            Ok(pair) => pair,
                             Err(e) => {
                                 return Err((
                                     etl::error::ErrorKind::Unknown,
@@ -85,8 +203,47 @@ impl Destination for DestinationEnum {
                                 )
                                     .into());
                             }
+                        };
+
+                        if let Err(err) = result {
+                            let Some(dest_id) = dest_id else {
+                                // A nested Multi failed as a whole; it already
+                                // routed its own children's failures to their
+                                // own DLQ entries, so there is nothing further
+                                // to queue here.
+                                warn!(%err, "Nested Multi destination failed");
+                                continue;
+                            };
+
+                            warn!(pipeline_dest_id = dest_id, %err, "Destination failed write_table_rows; queuing to DLQ");
+                            // Model a table-rows batch as synthetic inserts so
+                            // it can be replayed through the same DlqStore the
+                            // CDC path uses.
+                            let synthetic_events: Vec<Event> = rows
+                                .iter()
+                                .cloned()
+                                .map(|row| {
+                                    Event::Insert(etl::types::InsertEvent {
+                                        table_id,
+                                        commit_lsn: 0.into(),
+                                        table_row: row,
+                                    })
+                                })
+                                .collect();
+                            if let Err(push_err) = dlq
+                                .push(dest_id, &dlq_table_key(table_id), synthetic_events)
+                                .await
+                            {
+                                warn!(pipeline_dest_id = dest_id, %push_err, "Failed to queue rows to DLQ");
+                            }
+                            degraded.push(dest_id);
                         }
                     }
+
+                    if !degraded.is_empty() {
+                        warn!(?degraded, "Multi write_table_rows degraded for some destinations, failures queued to DLQ");
+                    }
+
                     Ok(())
                 }
             }
@@ -98,20 +255,36 @@ impl Destination for DestinationEnum {
         events: Vec<Event>,
     ) -> std::pin::Pin<Box<dyn std::future::Future<Output = EtlResult<()>> + Send + '_>> {
         Box::pin(async move {
+            let retry_config = RetryConfig::default();
             match self {
-                DestinationEnum::Snowflake(d) => d.write_events(events).await,
-                DestinationEnum::Postgres(d) => d.write_events(events).await,
-                DestinationEnum::Multi(dests) => {
+                DestinationEnum::Snowflake { inner, .. } => {
+                    let start = std::time::Instant::now();
+                    let result = with_retry(&retry_config, || inner.write_events(events.clone())).await;
+                    crate::admin::metrics().observe_write_latency("snowflake", start.elapsed());
+                    result
+                }
+                DestinationEnum::Postgres { inner, .. } => {
+                    let start = std::time::Instant::now();
+                    let result = with_retry(&retry_config, || inner.write_events(events.clone())).await;
+                    crate::admin::metrics().observe_write_latency("postgres", start.elapsed());
+                    result
+                }
+                DestinationEnum::Multi { dests, dlq } => {
                     let mut handles = vec![];
                     for dest in dests.iter() {
                         let dest = dest.clone();
                         let evs = events.clone();
-                        handles.push(tokio::spawn(async move { dest.write_events(evs).await }));
+                        let retry_config = retry_config.clone();
+                        handles.push(tokio::spawn(async move {
+                            let result = with_retry(&retry_config, || dest.write_events(evs.clone())).await;
+                            (dest.pipeline_dest_id(), evs, result)
+                        }));
                     }
 
+                    let mut degraded = Vec::new();
                     for h in handles {
-                        match h.await {
-                            Ok(res) => res?,
+                        let (dest_id, evs, result) = match h.await {
+                            Ok(triple) => triple,
                             Err(e) => {
                                 return Err((
                                     etl::error::ErrorKind::Unknown,
@@ -120,8 +293,31 @@ impl Destination for DestinationEnum {
                                 )
                                     .into());
                             }
+                        };
+
+                        if let Err(err) = result {
+                            let Some(dest_id) = dest_id else {
+                                warn!(%err, "Nested Multi destination failed");
+                                continue;
+                            };
+
+                            warn!(pipeline_dest_id = dest_id, %err, "Destination failed write_events; queuing to DLQ");
+                            for (table_id, table_events) in group_events_by_table(evs) {
+                                if let Err(push_err) = dlq
+                                    .push(dest_id, &dlq_table_key(table_id), table_events)
+                                    .await
+                                {
+                                    warn!(pipeline_dest_id = dest_id, %push_err, "Failed to queue events to DLQ");
+                                }
+                            }
+                            degraded.push(dest_id);
                         }
                     }
+
+                    if !degraded.is_empty() {
+                        warn!(?degraded, "Multi write_events degraded for some destinations, failures queued to DLQ");
+                    }
+
                     Ok(())
                 }
             }
@@ -0,0 +1,80 @@
+//! JSONL bulk export/import of the DLQ, mirroring a bulk-loader workflow so
+//! operators can inspect, filter, edit, and re-inject failed batches with
+//! standard command-line tooling (`dlq export | jq ... | dlq import`).
+//!
+//! Both directions are line-by-line: `export` writes one line per queued
+//! batch as it walks the store, and `import` pushes each line back to the
+//! DLQ as it's read, so neither side has to hold a multi-gigabyte queue in
+//! memory at once.
+
+use crate::dlq::serialize::{self, SerializableEvent};
+use crate::dlq::DlqStore;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+/// One line of `dlq export`/`dlq import` JSONL.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DlqRecord {
+    pub dest_id: i32,
+    pub table: String,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub events: Vec<SerializableEvent>,
+}
+
+/// Streams every queued batch matching the optional filters to `out` as
+/// JSONL, one object per line.
+pub async fn export(
+    dlq: &DlqStore,
+    mut out: impl Write,
+    filter_dest_id: Option<i32>,
+    filter_table: Option<&str>,
+) -> Result<u64> {
+    let written = dlq
+        .export_entries(filter_dest_id, filter_table, |dest_id, table, timestamp, events| {
+            let serializable_events: Vec<SerializableEvent> = events
+                .iter()
+                .filter_map(|e| SerializableEvent::try_from(e).ok())
+                .collect();
+
+            let record = DlqRecord {
+                dest_id,
+                table: table.to_string(),
+                timestamp,
+                events: serializable_events,
+            };
+
+            let line = serde_json::to_string(&record).context("Failed to encode DLQ record as JSON")?;
+            writeln!(out, "{}", line).context("Failed to write DLQ export line")?;
+            Ok(())
+        })
+        .await?;
+
+    out.flush().context("Failed to flush DLQ export output")?;
+    Ok(written)
+}
+
+/// Reads JSONL from `input` line by line and pushes each record back into
+/// `dlq` via `DlqStore::push`.
+pub async fn import(dlq: &DlqStore, input: impl AsyncBufRead + Unpin) -> Result<u64> {
+    let mut lines = input.lines();
+    let mut imported = 0u64;
+
+    while let Some(line) = lines.next_line().await.context("Failed to read DLQ import line")? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record: DlqRecord =
+            serde_json::from_str(&line).context("Failed to decode DLQ import line as JSON")?;
+        let events = record.events.iter().map(serialize::to_event).collect();
+
+        dlq.push(record.dest_id, &record.table, events)
+            .await
+            .context("Failed to push imported DLQ record")?;
+        imported += 1;
+    }
+
+    Ok(imported)
+}
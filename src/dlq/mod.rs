@@ -0,0 +1,10 @@
+//! Dead Letter Queue: durable storage and replay for events that failed to
+//! reach a destination.
+
+pub mod bulk;
+pub mod replay;
+pub mod serialize;
+pub mod store;
+
+pub use serialize::SerializableEvent;
+pub use store::DlqStore;
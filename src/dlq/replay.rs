@@ -0,0 +1,204 @@
+//! Drains `DlqStore` back into live destinations.
+//!
+//! Runs in two modes: an online background task started alongside
+//! `monitor::start` that keeps nibbling at the queue while the pipeline is
+//! up, and an offline one-shot drain used by the `repair-dlq` CLI
+//! subcommand to recover a backlog without starting the full
+//! `PipelineManager`.
+
+use crate::destination_enum::DestinationEnum;
+use crate::dlq::DlqStore;
+use etl::destination::Destination;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How many batches to pop from a single `(dest_id, table)` queue per pass,
+/// so one backed-up table doesn't starve the others.
+const BATCH_LIMIT: usize = 16;
+
+/// Default pause between online drain passes.
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Backoff base/cap for a `(dest_id, table)` that keeps failing replay:
+/// `BACKOFF_BASE * 2^attempts`, capped at `BACKOFF_MAX`. Mirrors the shape
+/// of `snowflake::retry_queue`'s `backoff_secs`, but lives only in-memory
+/// for the life of the drainer - a restart just resets it to zero, which is
+/// fine since a genuinely down sink will re-accumulate backoff immediately.
+const BACKOFF_BASE: Duration = Duration::from_secs(5);
+const BACKOFF_MAX: Duration = Duration::from_secs(300);
+
+/// Tracks per-`(dest_id, table)` replay attempts so a permanently-down sink's
+/// backlog isn't popped, failed, and re-queued on every single drain pass.
+#[derive(Default)]
+pub struct ReplayBackoff {
+    state: HashMap<(i32, String), (u32, Instant)>,
+}
+
+impl ReplayBackoff {
+    fn is_ready(&self, key: &(i32, String)) -> bool {
+        match self.state.get(key) {
+            Some((_, next_attempt_at)) => Instant::now() >= *next_attempt_at,
+            None => true,
+        }
+    }
+
+    fn record_failure(&mut self, key: (i32, String)) {
+        let attempts = self.state.get(&key).map(|(a, _)| a + 1).unwrap_or(1);
+        let delay = BACKOFF_BASE.saturating_mul(1u32 << attempts.min(10)).min(BACKOFF_MAX);
+        self.state.insert(key, (attempts, Instant::now() + delay));
+    }
+
+    fn record_success(&mut self, key: &(i32, String)) {
+        self.state.remove(key);
+    }
+}
+
+/// Outcome of a single drain pass, reported by both the online and offline
+/// modes so operators can see how much of the backlog actually moved.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReplayStats {
+    pub replayed: u64,
+    pub skipped: u64,
+    pub failed: u64,
+}
+
+impl ReplayStats {
+    fn merge(&mut self, other: ReplayStats) {
+        self.replayed += other.replayed;
+        self.skipped += other.skipped;
+        self.failed += other.failed;
+    }
+}
+
+/// Drains `dlq` into `destinations` (keyed by `pipeline_dest_id`) once,
+/// re-attempting each popped batch through the same retry/backoff path a
+/// live write would use, and re-queuing it (so a permanently-down sink
+/// doesn't spin hot) on repeated failure.
+pub async fn drain_once(
+    dlq: &DlqStore,
+    destinations: &HashMap<i32, DestinationEnum>,
+    backoff: &mut ReplayBackoff,
+) -> ReplayStats {
+    let mut stats = ReplayStats::default();
+
+    for dest_id in dlq.pending_destinations().await {
+        let Some(destination) = destinations.get(&dest_id) else {
+            warn!(
+                pipeline_dest_id = dest_id,
+                "DLQ has pending entries for a destination that isn't configured; skipping"
+            );
+            stats.skipped += 1;
+            continue;
+        };
+
+        for table in dlq.get_pending_tables(dest_id).await {
+            let key = (dest_id, table.clone());
+            if !backoff.is_ready(&key) {
+                stats.skipped += 1;
+                continue;
+            }
+
+            let events = match dlq.pop_batch(dest_id, &table, BATCH_LIMIT).await {
+                Ok(events) => events,
+                Err(err) => {
+                    warn!(pipeline_dest_id = dest_id, table = %table, %err, "Failed to pop DLQ batch");
+                    stats.failed += 1;
+                    continue;
+                }
+            };
+
+            if events.is_empty() {
+                continue;
+            }
+
+            let batch_size = events.len();
+            // `DestinationEnum::write_events` already wraps its own inner
+            // call(s) in `with_retry`, so retrying here too would double
+            // the backoff budget spent on one batch before giving up.
+            match destination.write_events(events.clone()).await {
+                Ok(()) => {
+                    info!(pipeline_dest_id = dest_id, table = %table, batch_size, "Replayed DLQ batch");
+                    stats.replayed += 1;
+                    backoff.record_success(&key);
+                    crate::admin::metrics().inc_replayed();
+                }
+                Err(err) => {
+                    warn!(pipeline_dest_id = dest_id, table = %table, %err, "Replay failed, re-queuing batch with backoff");
+                    if let Err(push_err) = dlq.push(dest_id, &table, events).await {
+                        warn!(pipeline_dest_id = dest_id, table = %table, %push_err, "Failed to re-queue DLQ batch after failed replay");
+                    }
+                    backoff.record_failure(key);
+                    stats.failed += 1;
+                    crate::admin::metrics().inc_failed();
+                }
+            }
+        }
+    }
+
+    stats
+}
+
+/// Spawns the online background drainer. Meant to be started next to
+/// `monitor::start` so the DLQ is continuously nibbled at while the
+/// pipeline is running.
+pub fn spawn_background(
+    dlq: DlqStore,
+    destinations: Arc<HashMap<i32, DestinationEnum>>,
+) -> tokio::task::JoinHandle<()> {
+    spawn_background_with_interval(dlq, destinations, DEFAULT_POLL_INTERVAL)
+}
+
+pub fn spawn_background_with_interval(
+    dlq: DlqStore,
+    destinations: Arc<HashMap<i32, DestinationEnum>>,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        let mut backoff = ReplayBackoff::default();
+        loop {
+            ticker.tick().await;
+            let stats = drain_once(&dlq, &destinations, &mut backoff).await;
+            if stats.replayed > 0 || stats.failed > 0 {
+                info!(
+                    replayed = stats.replayed,
+                    skipped = stats.skipped,
+                    failed = stats.failed,
+                    "DLQ background drain pass complete"
+                );
+            }
+        }
+    })
+}
+
+/// One-shot offline drain for the `repair-dlq` CLI subcommand: opens the
+/// fjall-backed DLQ at `dlq_base_path` without starting the full
+/// `PipelineManager`, reports pending counts, then drains once.
+pub async fn repair(dlq_base_path: &std::path::Path, destinations: HashMap<i32, DestinationEnum>) -> anyhow::Result<ReplayStats> {
+    let dlq = DlqStore::new(dlq_base_path)?;
+
+    let mut total_stats = ReplayStats::default();
+    for dest_id in dlq.pending_destinations().await {
+        let tables = dlq.get_pending_tables(dest_id).await;
+        let count = dlq.count_for_destination(dest_id).await;
+        info!(
+            pipeline_dest_id = dest_id,
+            pending_batches = count,
+            tables = ?tables,
+            "DLQ backlog before repair"
+        );
+    }
+
+    let mut backoff = ReplayBackoff::default();
+    total_stats.merge(drain_once(&dlq, &destinations, &mut backoff).await);
+    info!(
+        replayed = total_stats.replayed,
+        skipped = total_stats.skipped,
+        failed = total_stats.failed,
+        "DLQ repair drain complete"
+    );
+
+    Ok(total_stats)
+}
@@ -0,0 +1,219 @@
+//! Manual `Event` <-> JSON bridge.
+//!
+//! `etl::types::Event` (and the `Cell`/`TableRow` types it carries) don't
+//! implement `Serialize`/`Deserialize`, so the DLQ can't persist them
+//! directly. `SerializableEvent` is a hand-rolled mirror that covers the
+//! variants we actually route through the DLQ (insert/update/delete/
+//! truncate) and knows how to convert to/from the real `Event` type.
+
+use anyhow::{anyhow, Result};
+use etl::types::{Cell, Event, TableId, TableRow};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A lossy-but-faithful mirror of `Cell` that can be serialized.
+///
+/// Values that round-trip through a canonical string form (dates, numerics,
+/// uuids) are stored as strings rather than re-deriving the original typed
+/// representation, matching the same canonicalization `ValueEncoder`
+/// already uses for the Snowflake path.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SerializableCell {
+    Null,
+    Bool(bool),
+    String(String),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Bytes(Vec<u8>),
+    Json(Value),
+    Numeric(String),
+    Uuid(String),
+    Date(String),
+    Time(String),
+    Timestamp(String),
+    TimestampTz(String),
+    /// Any `Cell::Array(..)` variant, flattened to its JSON representation.
+    /// Arrays are only ever replayed back into a destination's JSON
+    /// conversion path, so we don't need a typed round trip for them.
+    Array(Value),
+}
+
+impl From<&Cell> for SerializableCell {
+    fn from(cell: &Cell) -> Self {
+        match cell {
+            Cell::Null => SerializableCell::Null,
+            Cell::Bool(v) => SerializableCell::Bool(*v),
+            Cell::String(v) => SerializableCell::String(v.clone()),
+            Cell::I16(v) => SerializableCell::I16(*v),
+            Cell::I32(v) => SerializableCell::I32(*v),
+            Cell::I64(v) => SerializableCell::I64(*v),
+            Cell::F32(v) => SerializableCell::F32(*v),
+            Cell::F64(v) => SerializableCell::F64(*v),
+            Cell::Bytes(v) => SerializableCell::Bytes(v.clone()),
+            Cell::Json(v) => SerializableCell::Json(v.clone()),
+            Cell::Numeric(v) => SerializableCell::Numeric(v.to_string()),
+            Cell::Uuid(v) => SerializableCell::Uuid(v.to_string()),
+            Cell::Date(v) => SerializableCell::Date(v.to_string()),
+            Cell::Time(v) => SerializableCell::Time(v.to_string()),
+            Cell::Timestamp(v) => SerializableCell::Timestamp(v.to_string()),
+            Cell::TimestampTz(v) => SerializableCell::TimestampTz(v.to_rfc3339()),
+            Cell::Array(_) => SerializableCell::Array(crate::snowflake::ValueEncoder::default().cell_to_json_value(cell)),
+            _ => SerializableCell::Json(crate::snowflake::ValueEncoder::default().cell_to_json_value(cell)),
+        }
+    }
+}
+
+impl TryFrom<&SerializableCell> for Cell {
+    type Error = anyhow::Error;
+
+    fn try_from(cell: &SerializableCell) -> Result<Self> {
+        Ok(match cell {
+            SerializableCell::Null => Cell::Null,
+            SerializableCell::Bool(v) => Cell::Bool(*v),
+            SerializableCell::String(v) => Cell::String(v.clone()),
+            SerializableCell::I16(v) => Cell::I16(*v),
+            SerializableCell::I32(v) => Cell::I32(*v),
+            SerializableCell::I64(v) => Cell::I64(*v),
+            SerializableCell::F32(v) => Cell::F32(*v),
+            SerializableCell::F64(v) => Cell::F64(*v),
+            SerializableCell::Bytes(v) => Cell::Bytes(v.clone()),
+            SerializableCell::Json(v) => Cell::Json(v.clone()),
+            // Numeric/Uuid/Date/Time/Timestamp/Array only need to survive a
+            // round trip into a destination's JSON conversion, so we hand
+            // them back as their canonical string/JSON form rather than
+            // re-parsing into the original typed `Cell` variant.
+            SerializableCell::Numeric(v) => Cell::String(v.clone()),
+            SerializableCell::Uuid(v) => Cell::String(v.clone()),
+            SerializableCell::Date(v) => Cell::String(v.clone()),
+            SerializableCell::Time(v) => Cell::String(v.clone()),
+            SerializableCell::Timestamp(v) => Cell::String(v.clone()),
+            SerializableCell::TimestampTz(v) => Cell::String(v.clone()),
+            SerializableCell::Array(v) => Cell::Json(v.clone()),
+        })
+    }
+}
+
+/// A serializable `TableRow`: just the ordered list of cell values.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SerializableRow {
+    pub values: Vec<SerializableCell>,
+}
+
+impl From<&TableRow> for SerializableRow {
+    fn from(row: &TableRow) -> Self {
+        SerializableRow {
+            values: row.values.iter().map(SerializableCell::from).collect(),
+        }
+    }
+}
+
+impl From<&SerializableRow> for TableRow {
+    fn from(row: &SerializableRow) -> Self {
+        TableRow {
+            values: row
+                .values
+                .iter()
+                .map(|c| Cell::try_from(c).unwrap_or(Cell::Null))
+                .collect(),
+        }
+    }
+}
+
+/// A serializable mirror of `etl::types::Event`, covering the DML variants
+/// the DLQ actually queues plus `Truncate` (no row payload, just the table).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SerializableEvent {
+    Insert {
+        table_id: u32,
+        lsn: u64,
+        row: SerializableRow,
+    },
+    Update {
+        table_id: u32,
+        lsn: u64,
+        row: SerializableRow,
+    },
+    Delete {
+        table_id: u32,
+        lsn: u64,
+        old_row: Option<SerializableRow>,
+    },
+    Truncate {
+        table_id: u32,
+        lsn: u64,
+    },
+}
+
+impl SerializableEvent {
+    pub fn table_id(&self) -> u32 {
+        match self {
+            SerializableEvent::Insert { table_id, .. }
+            | SerializableEvent::Update { table_id, .. }
+            | SerializableEvent::Delete { table_id, .. }
+            | SerializableEvent::Truncate { table_id, .. } => *table_id,
+        }
+    }
+}
+
+impl TryFrom<&Event> for SerializableEvent {
+    type Error = anyhow::Error;
+
+    fn try_from(event: &Event) -> Result<Self> {
+        Ok(match event {
+            Event::Insert(i) => SerializableEvent::Insert {
+                table_id: i.table_id.0,
+                lsn: i.commit_lsn.into(),
+                row: SerializableRow::from(&i.table_row),
+            },
+            Event::Update(u) => SerializableEvent::Update {
+                table_id: u.table_id.0,
+                lsn: u.commit_lsn.into(),
+                row: SerializableRow::from(&u.table_row),
+            },
+            Event::Delete(d) => SerializableEvent::Delete {
+                table_id: d.table_id.0,
+                lsn: d.commit_lsn.into(),
+                old_row: d.old_table_row.as_ref().map(|(_, row)| SerializableRow::from(row)),
+            },
+            Event::Truncate(t) => SerializableEvent::Truncate {
+                table_id: t.table_id.0,
+                lsn: t.commit_lsn.into(),
+            },
+            other => return Err(anyhow!("Event variant {:?} is not DLQ-serializable", other)),
+        })
+    }
+}
+
+/// Reconstructs a best-effort `Event::Insert`/`Update`/`Delete` for replay.
+///
+/// Replay only needs to feed a destination's write path, so reconstructed
+/// events carry the table id and row data faithfully; `Truncate` replays as
+/// a bare table-id marker since it has no row payload.
+pub fn to_event(serializable: &SerializableEvent) -> Event {
+    use etl::types::{DeleteEvent, InsertEvent, TruncateEvent, UpdateEvent};
+
+    match serializable {
+        SerializableEvent::Insert { table_id, lsn, row } => Event::Insert(InsertEvent {
+            table_id: TableId(*table_id),
+            commit_lsn: (*lsn).into(),
+            table_row: TableRow::from(row),
+        }),
+        SerializableEvent::Update { table_id, lsn, row } => Event::Update(UpdateEvent {
+            table_id: TableId(*table_id),
+            commit_lsn: (*lsn).into(),
+            table_row: TableRow::from(row),
+        }),
+        SerializableEvent::Delete { table_id, lsn, old_row } => Event::Delete(DeleteEvent {
+            table_id: TableId(*table_id),
+            commit_lsn: (*lsn).into(),
+            old_table_row: old_row.as_ref().map(|r| (true, TableRow::from(r))),
+        }),
+        SerializableEvent::Truncate { table_id, lsn } => Event::Truncate(TruncateEvent {
+            table_id: TableId(*table_id),
+            commit_lsn: (*lsn).into(),
+        }),
+    }
+}
@@ -1,33 +1,81 @@
-//! DLQ Store - In-memory Dead Letter Queue with fjall metadata persistence
+//! DLQ Store - Dead Letter Queue with fjall-backed durable persistence
 //!
 //! Stores events that failed to write to destination due to connection errors.
-//! Events are kept in-memory (since Event from etl crate doesn't implement Serialize),
-//! while metadata (counts, error states) are persisted to fjall for durability.
+//! Events are kept in-memory for fast access, and every push is durably logged
+//! to a fjall `dlq_events` keyspace (plus periodic compacted snapshots) so the
+//! queue survives process restarts. See `SerializableEvent` for the manual
+//! `Event` <-> JSON bridge this relies on.
 
+use super::serialize::{self, SerializableEvent};
 use anyhow::{Context, Result};
 use etl::types::Event;
 use fjall::{Database, Keyspace, KeyspaceCreateOptions, PersistMode};
 use std::collections::{HashMap, VecDeque};
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::RwLock;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
+
+/// Number of pushes between compacted snapshots of the log.
+const CHECKPOINT_INTERVAL: u64 = 64;
+
+/// Which kind of record a `dlq_events` key refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RecordKind {
+    Log,
+    Snapshot,
+}
 
 /// Entry in the DLQ
-#[allow(dead_code)]
 struct DlqEntry {
     events: Vec<Event>,
     timestamp: chrono::DateTime<chrono::Utc>,
+    /// Monotonic sequence number, doubling as the fjall log key and the
+    /// idempotency key used to dedupe a crash between the in-memory mutation
+    /// and the fjall write on replay.
+    seq: u64,
+}
+
+/// On-disk representation of a `DlqEntry`, written to the `dlq_events`
+/// keyspace keyed by `(dest_id, table, seq)`.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PersistedEntry {
+    events: Vec<SerializableEvent>,
+    timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+/// A compacted snapshot of one destination/table queue, written every
+/// `CHECKPOINT_INTERVAL` pushes so replay doesn't have to fold the entire
+/// log from the beginning of time.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Snapshot {
+    /// Highest seq number folded into this snapshot; log records at or below
+    /// this seq are superseded and can be dropped on replay.
+    up_to_seq: u64,
+    entries: Vec<PersistedEntry>,
 }
 
 /// Dead Letter Queue store using in-memory queue with fjall metadata persistence
 pub struct DlqStore {
     /// In-memory queues per destination/table: (dest_id, table) -> queue of event batches
     queues: Arc<RwLock<HashMap<(i32, String), VecDeque<DlqEntry>>>>,
-    /// Fjall DB for metadata persistence (counts, last error times, etc)
+    /// Fjall DB for metadata and event persistence
     db: Arc<Database>,
-    /// Metadata keyspace
+    /// Metadata keyspace (counts, last error times, etc)
     metadata_ks: Keyspace,
+    /// Durable event log + snapshot keyspace
+    events_ks: Keyspace,
+    /// Global monotonic sequence counter, used as the idempotency key for
+    /// every pushed batch.
+    next_seq: Arc<AtomicU64>,
+    /// The current snapshot key per `(dest_id, table)` partition, if any.
+    /// `checkpoint` used to find and drop a partition's stale snapshots by
+    /// scanning the entire `events_ks` keyspace on every call (and
+    /// `pop_batch` calls `checkpoint` on every pop), making each pop
+    /// O(total keys) on a large queue. Tracking the one key that's
+    /// currently live lets `checkpoint` remove it directly instead.
+    snapshot_keys: Arc<RwLock<HashMap<(i32, String), String>>>,
 }
 
 impl Clone for DlqStore {
@@ -36,12 +84,16 @@ impl Clone for DlqStore {
             queues: self.queues.clone(),
             db: self.db.clone(),
             metadata_ks: self.metadata_ks.clone(),
+            events_ks: self.events_ks.clone(),
+            next_seq: self.next_seq.clone(),
+            snapshot_keys: self.snapshot_keys.clone(),
         }
     }
 }
 
 impl DlqStore {
-    /// Create a new DLQ store at the specified path
+    /// Create a new DLQ store at the specified path, replaying any
+    /// previously persisted events back into memory.
     pub fn new(base_path: &Path) -> Result<Self> {
         let dlq_path = base_path.join("dlq");
         std::fs::create_dir_all(&dlq_path)
@@ -58,15 +110,156 @@ impl DlqStore {
             .keyspace("dlq_metadata", || KeyspaceCreateOptions::default())
             .context("Failed to create metadata keyspace")?;
 
-        info!("DLQ store initialized at {:?}", dlq_path);
+        // Create durable event log/snapshot keyspace
+        let events_ks = db
+            .keyspace("dlq_events", || KeyspaceCreateOptions::default())
+            .context("Failed to create dlq_events keyspace")?;
+
+        let (queues, snapshot_keys, max_seq) = Self::replay(&events_ks)?;
+
+        info!(
+            "DLQ store initialized at {:?}, replayed {} queue(s) up to seq {}",
+            dlq_path,
+            queues.len(),
+            max_seq
+        );
 
         Ok(Self {
-            queues: Arc::new(RwLock::new(HashMap::new())),
+            queues: Arc::new(RwLock::new(queues)),
             db,
             metadata_ks,
+            events_ks,
+            next_seq: Arc::new(AtomicU64::new(max_seq + 1)),
+            snapshot_keys: Arc::new(RwLock::new(snapshot_keys)),
         })
     }
 
+    /// Replays the latest snapshot plus any trailing log records for every
+    /// `(dest_id, table)` partition back into an in-memory queue map.
+    ///
+    /// Key ordering within a partition is `log:<seq>` before `snap:<seq>`
+    /// (`"log" < "snap"` lexicographically), so records can't simply be
+    /// folded in iteration order - that would apply every log record before
+    /// the snapshot that already supersedes it and wipe it with
+    /// `queue.clear()`. Instead, gather the highest-seq snapshot and every
+    /// log record per partition first, then seed the queue from the
+    /// snapshot and only replay logs with `seq > snapshot.up_to_seq`.
+    #[allow(clippy::type_complexity)]
+    fn replay(
+        events_ks: &Keyspace,
+    ) -> Result<(
+        HashMap<(i32, String), VecDeque<DlqEntry>>,
+        HashMap<(i32, String), String>,
+        u64,
+    )> {
+        struct PartitionState {
+            snapshot: Option<(u64, Vec<PersistedEntry>)>,
+            snapshot_key: Option<String>,
+            logs: Vec<(u64, PersistedEntry)>,
+        }
+
+        let mut partitions: HashMap<(i32, String), PartitionState> = HashMap::new();
+        let mut max_seq = 0u64;
+
+        for kv in events_ks.iter() {
+            let (key_bytes, value_bytes) = kv.context("Failed to read dlq_events record")?;
+            let key = String::from_utf8_lossy(&key_bytes);
+            let Some((dest_id, table, record_kind, seq)) = Self::parse_event_key(&key) else {
+                warn!("DLQ: skipping unparsable event key {}", key);
+                continue;
+            };
+
+            max_seq = max_seq.max(seq);
+            let state = partitions
+                .entry((dest_id, table))
+                .or_insert_with(|| PartitionState { snapshot: None, snapshot_key: None, logs: Vec::new() });
+
+            match record_kind {
+                RecordKind::Snapshot => {
+                    let snapshot: Snapshot = serde_json::from_slice(&value_bytes)
+                        .context("Failed to decode DLQ snapshot")?;
+                    // Older builds could leave more than one snapshot behind
+                    // for a partition; only the highest-seq one is current.
+                    let supersedes = match &state.snapshot {
+                        Some((up_to, _)) => snapshot.up_to_seq > *up_to,
+                        None => true,
+                    };
+                    if supersedes {
+                        state.snapshot_key = Some(key.into_owned());
+                        state.snapshot = Some((snapshot.up_to_seq, snapshot.entries));
+                    }
+                }
+                RecordKind::Log => {
+                    let persisted: PersistedEntry = serde_json::from_slice(&value_bytes)
+                        .context("Failed to decode DLQ log record")?;
+                    state.logs.push((seq, persisted));
+                }
+            }
+        }
+
+        let mut queues: HashMap<(i32, String), VecDeque<DlqEntry>> = HashMap::new();
+        let mut snapshot_keys: HashMap<(i32, String), String> = HashMap::new();
+        for (key, mut state) in partitions {
+            let up_to_seq = state.snapshot.as_ref().map(|(seq, _)| *seq).unwrap_or(0);
+            let mut queue = VecDeque::new();
+
+            if let Some(snap_key) = state.snapshot_key.take() {
+                snapshot_keys.insert(key.clone(), snap_key);
+            }
+
+            if let Some((_, entries)) = state.snapshot.take() {
+                for entry in entries {
+                    queue.push_back(DlqEntry {
+                        events: entry.events.iter().map(serialize::to_event).collect(),
+                        timestamp: entry.timestamp,
+                        seq: up_to_seq,
+                    });
+                }
+            }
+
+            // Only the trailing log records the snapshot hasn't folded in
+            // yet are replayed, oldest first.
+            state.logs.sort_by_key(|(seq, _)| *seq);
+            for (seq, persisted) in state.logs {
+                if seq <= up_to_seq {
+                    continue;
+                }
+                queue.push_back(DlqEntry {
+                    events: persisted.events.iter().map(serialize::to_event).collect(),
+                    timestamp: persisted.timestamp,
+                    seq,
+                });
+            }
+
+            queues.insert(key, queue);
+        }
+
+        Ok((queues, snapshot_keys, max_seq))
+    }
+
+    fn event_key(pipeline_dest_id: i32, table_name: &str, kind: RecordKind, seq: u64) -> String {
+        let tag = match kind {
+            RecordKind::Log => "log",
+            RecordKind::Snapshot => "snap",
+        };
+        format!("{}:{}:{}:{:020}", pipeline_dest_id, table_name, tag, seq)
+    }
+
+    fn parse_event_key(key: &str) -> Option<(i32, String, RecordKind, u64)> {
+        let mut parts = key.rsplitn(3, ':');
+        let seq: u64 = parts.next()?.parse().ok()?;
+        let tag = parts.next()?;
+        let rest = parts.next()?;
+        let kind = match tag {
+            "log" => RecordKind::Log,
+            "snap" => RecordKind::Snapshot,
+            _ => return None,
+        };
+        let (dest_str, table) = rest.split_once(':')?;
+        let dest_id: i32 = dest_str.parse().ok()?;
+        Some((dest_id, table.to_string(), kind, seq))
+    }
+
     /// Generate key for a destination/table combination
     fn make_key(pipeline_dest_id: i32, table_name: &str) -> (i32, String) {
         (pipeline_dest_id, table_name.to_string())
@@ -78,19 +271,50 @@ impl DlqStore {
             return Ok(());
         }
 
-        let key = Self::make_key(pipeline_dest_id, table_name);
-        let entry = DlqEntry {
-            events,
-            timestamp: chrono::Utc::now(),
+        let seq = self.next_seq.fetch_add(1, Ordering::SeqCst);
+        let timestamp = chrono::Utc::now();
+
+        let serializable: Vec<SerializableEvent> = events
+            .iter()
+            .filter_map(|e| match SerializableEvent::try_from(e) {
+                Ok(s) => Some(s),
+                Err(err) => {
+                    warn!("DLQ: dropping non-serializable event from durable log: {}", err);
+                    None
+                }
+            })
+            .collect();
+
+        // Write the log record first so a crash after this point but before
+        // the in-memory mutation just means the entry gets replayed on
+        // restart rather than lost; the seq-based dedupe in `replay` makes
+        // that safe to do unconditionally.
+        let log_key = Self::event_key(pipeline_dest_id, table_name, RecordKind::Log, seq);
+        let persisted = PersistedEntry {
+            events: serializable,
+            timestamp,
         };
+        self.events_ks
+            .insert(&log_key, serde_json::to_vec(&persisted).context("Failed to encode DLQ log record")?)
+            .context("Failed to write DLQ log record")?;
 
+        let key = Self::make_key(pipeline_dest_id, table_name);
         let mut queues = self.queues.write().await;
-        queues.entry(key.clone()).or_insert_with(VecDeque::new).push_back(entry);
+        queues
+            .entry(key.clone())
+            .or_insert_with(VecDeque::new)
+            .push_back(DlqEntry { events, timestamp, seq });
 
         // Update metadata (count)
         let count = queues.get(&key).map(|q| q.len()).unwrap_or(0);
         self.update_count_metadata(pipeline_dest_id, table_name, count)?;
 
+        if count as u64 % CHECKPOINT_INTERVAL == 0 {
+            if let Some(queue) = queues.get(&key) {
+                self.checkpoint(pipeline_dest_id, table_name, queue).await?;
+            }
+        }
+
         debug!(
             "DLQ: Pushed events for dest {} table {}, queue size: {}",
             pipeline_dest_id,
@@ -101,6 +325,57 @@ impl DlqStore {
         Ok(())
     }
 
+    /// Writes a compacted snapshot of the current queue and truncates the
+    /// log records it supersedes. Called every `CHECKPOINT_INTERVAL` pushes
+    /// (and on every `pop_batch`) to bound write amplification from the
+    /// ever-growing log.
+    async fn checkpoint(&self, pipeline_dest_id: i32, table_name: &str, queue: &VecDeque<DlqEntry>) -> Result<()> {
+        let up_to_seq = queue.back().map(|e| e.seq).unwrap_or(0);
+
+        let entries: Vec<PersistedEntry> = queue
+            .iter()
+            .map(|entry| PersistedEntry {
+                events: entry
+                    .events
+                    .iter()
+                    .filter_map(|e| SerializableEvent::try_from(e).ok())
+                    .collect(),
+                timestamp: entry.timestamp,
+            })
+            .collect();
+
+        let snapshot = Snapshot { up_to_seq, entries };
+        let snap_key = Self::event_key(pipeline_dest_id, table_name, RecordKind::Snapshot, up_to_seq);
+        self.events_ks
+            .insert(&snap_key, serde_json::to_vec(&snapshot).context("Failed to encode DLQ snapshot")?)
+            .context("Failed to write DLQ snapshot")?;
+
+        // Superseded log records (everything at or below up_to_seq) can now
+        // be dropped; only the snapshot is needed to replay this state.
+        for seq in queue.iter().map(|e| e.seq).filter(|s| *s <= up_to_seq) {
+            let log_key = Self::event_key(pipeline_dest_id, table_name, RecordKind::Log, seq);
+            let _ = self.events_ks.remove(&log_key);
+        }
+
+        // Drop this partition's previous snapshot, if any. Without this a
+        // later checkpoint that writes a *lower* up_to_seq (e.g. right after
+        // `pop_batch` empties the queue, which writes up_to_seq 0) would still sort behind a
+        // stale higher-seq snapshot on replay and resurrect entries this one
+        // already dropped. `snapshot_keys` tracks the one live key per
+        // partition directly, so this no longer has to scan `events_ks` for
+        // every checkpoint (`pop_batch` calls this on every pop).
+        let partition = Self::make_key(pipeline_dest_id, table_name);
+        let mut snapshot_keys = self.snapshot_keys.write().await;
+        if let Some(old_key) = snapshot_keys.insert(partition, snap_key.clone()) {
+            if old_key != snap_key {
+                let _ = self.events_ks.remove(&old_key);
+            }
+        }
+
+        let _ = self.db.persist(PersistMode::Buffer);
+        Ok(())
+    }
+
     /// Pop a batch of events from the DLQ (oldest first)
     /// Returns events and removes them from the store
     pub async fn pop_batch(&self, pipeline_dest_id: i32, table_name: &str, limit: usize) -> Result<Vec<Event>> {
@@ -108,11 +383,13 @@ impl DlqStore {
         let mut queues = self.queues.write().await;
 
         let mut all_events = Vec::new();
-        
+
         if let Some(queue) = queues.get_mut(&key) {
             let mut batches_to_take = limit;
             while batches_to_take > 0 && !queue.is_empty() {
                 if let Some(entry) = queue.pop_front() {
+                    let log_key = Self::event_key(pipeline_dest_id, table_name, RecordKind::Log, entry.seq);
+                    let _ = self.events_ks.remove(&log_key);
                     all_events.extend(entry.events);
                     batches_to_take -= 1;
                 }
@@ -122,6 +399,11 @@ impl DlqStore {
             let remaining = queue.len();
             self.update_count_metadata(pipeline_dest_id, table_name, remaining)?;
 
+            // The queue shrank, so the last checkpoint (if any) is stale;
+            // write a fresh one so a restart right after this pop doesn't
+            // resurrect entries we just drained.
+            self.checkpoint(pipeline_dest_id, table_name, queue).await?;
+
             debug!(
                 "DLQ: Popped {} events for dest {} table {}, remaining: {}",
                 all_events.len(),
@@ -151,6 +433,50 @@ impl DlqStore {
             .sum()
     }
 
+    /// Read-only walk of every queued batch, optionally filtered by
+    /// destination id and/or table name, calling `visit` once per batch
+    /// without removing anything from the queue. Used by the `dlq export`
+    /// bulk tool; `visit` is handed a borrow of the batch's events rather
+    /// than the whole matching set being collected into memory first, so a
+    /// multi-gigabyte queue can be streamed out one batch at a time.
+    pub async fn export_entries(
+        &self,
+        filter_dest_id: Option<i32>,
+        filter_table: Option<&str>,
+        mut visit: impl FnMut(i32, &str, chrono::DateTime<chrono::Utc>, &[Event]) -> Result<()>,
+    ) -> Result<u64> {
+        let queues = self.queues.read().await;
+        let mut visited = 0u64;
+        for ((dest_id, table), queue) in queues.iter() {
+            if filter_dest_id.is_some_and(|id| id != *dest_id) {
+                continue;
+            }
+            if filter_table.is_some_and(|t| t != table) {
+                continue;
+            }
+            for entry in queue {
+                visit(*dest_id, table, entry.timestamp, &entry.events)?;
+                visited += 1;
+            }
+        }
+        Ok(visited)
+    }
+
+    /// All destination ids with at least one non-empty queue. Used by the
+    /// replay drainer and the admin metrics endpoint to discover what to
+    /// scrape without the caller having to track dest ids itself.
+    pub async fn pending_destinations(&self) -> Vec<i32> {
+        let queues = self.queues.read().await;
+        let mut dest_ids: Vec<i32> = queues
+            .iter()
+            .filter(|(_, queue)| !queue.is_empty())
+            .map(|((dest_id, _), _)| *dest_id)
+            .collect();
+        dest_ids.sort_unstable();
+        dest_ids.dedup();
+        dest_ids
+    }
+
     /// Get all table names with pending DLQ entries for a destination
     pub async fn get_pending_tables(&self, pipeline_dest_id: i32) -> Vec<String> {
         let queues = self.queues.read().await;
@@ -193,14 +519,77 @@ impl DlqStore {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use etl::types::{Cell, InsertEvent, TableId, TableRow};
     use tempfile::tempdir;
 
     #[tokio::test]
     async fn test_dlq_store_basic() {
         let dir = tempdir().unwrap();
         let store = DlqStore::new(dir.path()).unwrap();
-        
+
         // Initially empty
         assert!(store.is_empty(1, "test_table").await);
     }
+
+    fn sample_event(n: i32) -> Event {
+        Event::Insert(InsertEvent {
+            table_id: TableId(1),
+            commit_lsn: 0.into(),
+            table_row: TableRow {
+                values: vec![Cell::I32(n)],
+            },
+        })
+    }
+
+    /// Pushes past `CHECKPOINT_INTERVAL` (writing at least one compacted
+    /// snapshot), reopens the store at the same path, and checks replay
+    /// folds the snapshot plus any trailing log records into exactly the
+    /// same queue - no entries dropped, none duplicated.
+    #[tokio::test]
+    async fn push_past_checkpoint_then_restart_replays_every_entry_once() {
+        let dir = tempdir().unwrap();
+        let pushed = CHECKPOINT_INTERVAL as i32 + 5;
+
+        {
+            let store = DlqStore::new(dir.path()).unwrap();
+            for n in 0..pushed {
+                store.push(1, "orders", vec![sample_event(n)]).await.unwrap();
+            }
+            assert_eq!(store.count_for_destination(1).await, pushed as usize);
+        }
+
+        let restarted = DlqStore::new(dir.path()).unwrap();
+        assert_eq!(restarted.count_for_destination(1).await, pushed as usize);
+
+        let replayed = restarted.pop_batch(1, "orders", pushed as usize).await.unwrap();
+        assert_eq!(replayed.len(), pushed as usize);
+        assert!(restarted.is_empty(1, "orders").await);
+    }
+
+    /// Pushes a few batches (fewer than `CHECKPOINT_INTERVAL`, so no push
+    /// writes a snapshot), pops some of them (which writes the only
+    /// snapshot and drops the popped log records), then restarts. The popped batches must not come back, and the remaining
+    /// ones must not be double-counted.
+    #[tokio::test]
+    async fn pop_then_restart_does_not_resurrect_or_double_count() {
+        let dir = tempdir().unwrap();
+
+        {
+            let store = DlqStore::new(dir.path()).unwrap();
+            for n in 0..5 {
+                store.push(1, "orders", vec![sample_event(n)]).await.unwrap();
+            }
+            // Drain the first 3 batches; only the last 2 should survive.
+            let popped = store.pop_batch(1, "orders", 3).await.unwrap();
+            assert_eq!(popped.len(), 3);
+            assert_eq!(store.count_for_destination(1).await, 2);
+        }
+
+        let restarted = DlqStore::new(dir.path()).unwrap();
+        assert_eq!(restarted.count_for_destination(1).await, 2);
+
+        let remaining = restarted.pop_batch(1, "orders", 10).await.unwrap();
+        assert_eq!(remaining.len(), 2);
+        assert!(restarted.is_empty(1, "orders").await);
+    }
 }
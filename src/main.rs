@@ -1,17 +1,101 @@
+mod admin;
+mod crypto;
+mod destination_enum;
+mod dlq;
 mod monitor;
+mod retry;
+mod snowflake;
 
 use anyhow::Result;
+use destination_enum::DestinationEnum;
 use dotenv::dotenv;
 use figlet_rs::FIGfont;
 use rosetta::manager::PipelineManager;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
 use tracing::info;
 use sqlx::postgres::PgPoolOptions;
 
+/// Builds the `pipeline_dest_id -> DestinationEnum` map, including for the
+/// offline `repair-dlq` path which doesn't start the manager. Only a
+/// Snowflake destination is added, and only when `SNOWFLAKE_PIPELINE_DEST_ID`
+/// is set; Postgres destinations are not built here.
+async fn configured_destinations(pg_pool: &sqlx::Pool<sqlx::Postgres>) -> Result<HashMap<i32, DestinationEnum>> {
+    let mut destinations = HashMap::new();
+
+    if let Ok(dest_id) = std::env::var("SNOWFLAKE_PIPELINE_DEST_ID") {
+        let dest_id: i32 = dest_id.parse().expect("SNOWFLAKE_PIPELINE_DEST_ID must be an integer");
+        let config = crate::config::SnowflakeConfig::from_env()?;
+        let inner = crate::snowflake::SnowflakeDestination::new(config, pg_pool.clone()).await?;
+        destinations.insert(dest_id, DestinationEnum::Snowflake { dest_id, inner });
+    }
+
+    Ok(destinations)
+}
+
+/// Looks up the value following a `--flag` in a raw argv slice.
+fn flag_value(args: &[String], flag: &str) -> Option<String> {
+    args.iter().position(|a| a == flag).and_then(|i| args.get(i + 1)).cloned()
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv().ok();
     tracing_subscriber::fmt::init();
 
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("dlq") {
+        let dlq_path = args
+            .get(3)
+            .map(PathBuf::from)
+            .expect("usage: dlq <export|import> <dlq-base-path> [--dest-id <id>] [--table <name>]");
+        let dlq_store = dlq::DlqStore::new(&dlq_path)?;
+
+        match args.get(2).map(String::as_str) {
+            Some("export") => {
+                let filter_dest_id = flag_value(&args, "--dest-id").and_then(|v| v.parse().ok());
+                let filter_table = flag_value(&args, "--table");
+                let stdout = std::io::stdout();
+                let count = dlq::bulk::export(
+                    &dlq_store,
+                    stdout.lock(),
+                    filter_dest_id,
+                    filter_table.as_deref(),
+                )
+                .await?;
+                eprintln!("Exported {} DLQ batch(es)", count);
+            }
+            Some("import") => {
+                let stdin = tokio::io::stdin();
+                let count = dlq::bulk::import(&dlq_store, tokio::io::BufReader::new(stdin)).await?;
+                eprintln!("Imported {} DLQ batch(es)", count);
+            }
+            _ => {
+                eprintln!("usage: dlq <export|import> <dlq-base-path> [--dest-id <id>] [--table <name>]");
+            }
+        }
+        return Ok(());
+    }
+
+    if args.get(1).map(String::as_str) == Some("repair-dlq") {
+        let dlq_path = args
+            .get(2)
+            .map(PathBuf::from)
+            .expect("usage: repair-dlq <dlq-base-path>");
+
+        let database_url = std::env::var("CONFIG_DATABASE_URL")
+            .expect("CONFIG_DATABASE_URL environment variable must be set");
+        let pool = PgPoolOptions::new()
+            .max_connections(5)
+            .connect(&database_url)
+            .await?;
+
+        let destinations = configured_destinations(&pool).await?;
+        dlq::replay::repair(&dlq_path, destinations).await?;
+        return Ok(());
+    }
+
     if let Ok(font) = FIGfont::from_file("assets/fonts/Slant.flf") {
         if let Some(figure) = font.convert("Rosetta") {
             println!("{}", figure);
@@ -23,7 +107,7 @@ async fn main() -> Result<()> {
         .expect("CONFIG_DATABASE_URL environment variable must be set");
 
     info!("Starting Rosetta Pipeline Manager...");
-   
+
     // Create database pool for monitor
     let pool = PgPoolOptions::new()
         .max_connections(10)
@@ -34,9 +118,57 @@ async fn main() -> Result<()> {
     monitor::start(pool.clone());
     info!("System monitor started in background");
 
-    // Start the pipeline manager (this will block)
-    let manager = PipelineManager::new(&database_url).await?;
-    manager.run().await?;
+    // `DlqStore` keeps its queues in memory (fjall only durably persists
+    // them); open it exactly once here with `DlqStore::new` and clone the
+    // handle everywhere it's needed (`Clone` is implemented to share the
+    // same `Arc`-backed queues/db, not to open a second independent store)
+    // rather than calling `new` again elsewhere. A second `DlqStore::new`
+    // at the same path would not observe batches pushed into this one, and
+    // may fail outright trying to reopen the same fjall path concurrently.
+    let dlq_base_path = std::env::var("DLQ_BASE_PATH").unwrap_or_else(|_| ".".to_string());
+    let dlq_store = dlq::DlqStore::new(&PathBuf::from(dlq_base_path))?;
+
+    // Build the destination set once and hand the same instances to every
+    // consumer (the live pipeline's `Multi` fan-out, the online drainer,
+    // and the admin API) so they all observe the same `DlqStore` and the
+    // same per-destination connections/buffers, instead of each standing
+    // up its own. `PipelineManager::new` takes `dlq_store` and
+    // `destinations` for exactly this reason: it must wrap the supplied
+    // map in its `DestinationEnum::Multi` rather than constructing a
+    // second, independent one internally. `rosetta::manager` isn't part of
+    // this source tree, so this signature isn't something this module can
+    // verify, only assume by convention (same caveat as `crate::snowflake`'s
+    // `SnowpipeClient` gap).
+    let destinations = Arc::new(configured_destinations(&pool).await?);
+
+    // Start the online DLQ drainer alongside the monitor
+    dlq::replay::spawn_background(dlq_store.clone(), destinations.clone());
+    info!("DLQ background drainer started");
+
+    // Start the admin HTTP API (Prometheus /metrics + /health)
+    admin::start(admin::configured_addr(), pool.clone(), dlq_store.clone());
+    admin::metrics().set_pipeline_alive(true);
+    info!("Admin HTTP API started");
+
+    // Start the pipeline manager (this will block), wired to the same
+    // `dlq_store` and `destinations` the drainer and admin API above are
+    // watching so the failures it queues are the ones they actually see.
+    let manager = PipelineManager::new(&database_url, dlq_store.clone(), destinations.clone()).await?;
+    let run_result = tokio::select! {
+        result = manager.run() => result,
+        _ = tokio::signal::ctrl_c() => {
+            info!("Received shutdown signal, flushing buffered destination writes before exiting...");
+            Ok(())
+        }
+    };
+
+    // Flush every destination's in-memory buffers (e.g. Snowflake's
+    // per-table micro-batches) before exiting so a clean shutdown doesn't
+    // silently drop whatever hadn't flushed yet.
+    for destination in destinations.values() {
+        destination.flush().await;
+    }
 
+    run_result?;
     Ok(())
 }
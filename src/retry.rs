@@ -0,0 +1,226 @@
+//! Transient-error classification and exponential-backoff retry for
+//! destination writes.
+//!
+//! Wraps a destination call so a briefly-down sink (connection refused/reset
+//! while Snowflake or Postgres is restarting, a load balancer cycling, etc.)
+//! is retried in-process instead of immediately falling through to the DLQ.
+//! Only errors classified as transient are retried; everything else is
+//! handed back to the caller on the first attempt so it can route straight
+//! to `DlqStore`.
+
+use etl::error::EtlResult;
+use rand::Rng;
+use std::future::Future;
+use std::io;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Whether a failed destination call is worth retrying.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// Likely to succeed on a subsequent attempt (connection drop, reset).
+    Transient,
+    /// Won't be fixed by retrying (bad credentials, malformed payload, ...).
+    Permanent,
+}
+
+/// Exponential backoff parameters for [`with_retry`].
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    /// Delay before the first retry.
+    pub base_interval: Duration,
+    /// Growth factor applied to the delay after each attempt.
+    pub multiplier: f64,
+    /// Randomized jitter applied on top of the computed delay, as a
+    /// fraction of it (e.g. 0.2 means +/-20%).
+    pub jitter: f64,
+    /// Once this much wall-clock time has elapsed since the first attempt,
+    /// stop retrying and return the last error as permanent.
+    pub max_elapsed: Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            base_interval: Duration::from_millis(200),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_elapsed: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Classifies the underlying cause of a destination failure as transient or
+/// permanent by inspecting the error chain for the connection-level
+/// `std::io::ErrorKind`s that sqlx and the Snowflake client surface when the
+/// remote end is briefly unreachable.
+pub fn classify_error(err: &(dyn std::error::Error + 'static)) -> ErrorClass {
+    let mut source = Some(err);
+    while let Some(e) = source {
+        if let Some(io_err) = e.downcast_ref::<io::Error>() {
+            return match io_err.kind() {
+                io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted => ErrorClass::Transient,
+                _ => ErrorClass::Permanent,
+            };
+        }
+        source = e.source();
+    }
+
+    // No io::Error in the chain (e.g. the failure never reached a socket) -
+    // treat it as permanent so we don't retry things like malformed
+    // payloads or auth errors.
+    ErrorClass::Permanent
+}
+
+/// Runs `op` with exponential backoff, retrying only transient failures
+/// (per [`classify_error`]) until `config.max_elapsed` has passed, at which
+/// point the last error is returned so the caller can route it to the DLQ.
+pub async fn with_retry<F, Fut, T>(config: &RetryConfig, mut op: F) -> EtlResult<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = EtlResult<T>>,
+{
+    let start = Instant::now();
+    let mut delay = config.base_interval;
+
+    loop {
+        match op().await {
+            Ok(value) => return Ok(value),
+            Err(err) => {
+                let class = classify_error(&err);
+                let elapsed = start.elapsed();
+
+                if class == ErrorClass::Permanent || elapsed + delay > config.max_elapsed {
+                    return Err(err);
+                }
+
+                let jittered = apply_jitter(delay, config.jitter);
+                warn!(
+                    "Transient destination error, retrying in {:?} (elapsed {:?}): {}",
+                    jittered, elapsed, err
+                );
+                tokio::time::sleep(jittered).await;
+
+                delay = delay.mul_f64(config.multiplier);
+            }
+        }
+    }
+}
+
+fn apply_jitter(delay: Duration, jitter: f64) -> Duration {
+    if jitter <= 0.0 {
+        return delay;
+    }
+    let factor = 1.0 + rand::rng().random_range(-jitter..=jitter);
+    delay.mul_f64(factor.max(0.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    /// An error whose `source()` is the given `io::Error`, so
+    /// `classify_error` has to walk the chain instead of matching directly.
+    #[derive(Debug)]
+    struct Wrapped(io::Error);
+
+    impl std::fmt::Display for Wrapped {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "wrapped: {}", self.0)
+        }
+    }
+
+    impl std::error::Error for Wrapped {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[derive(Debug)]
+    struct NoSource;
+
+    impl std::fmt::Display for NoSource {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "no source")
+        }
+    }
+
+    impl std::error::Error for NoSource {}
+
+    #[test]
+    fn connection_refused_is_transient() {
+        let err = io::Error::from(io::ErrorKind::ConnectionRefused);
+        assert_eq!(classify_error(&err), ErrorClass::Transient);
+    }
+
+    #[test]
+    fn connection_reset_is_transient() {
+        let err = io::Error::from(io::ErrorKind::ConnectionReset);
+        assert_eq!(classify_error(&err), ErrorClass::Transient);
+    }
+
+    #[test]
+    fn connection_aborted_is_transient() {
+        let err = io::Error::from(io::ErrorKind::ConnectionAborted);
+        assert_eq!(classify_error(&err), ErrorClass::Transient);
+    }
+
+    #[test]
+    fn other_io_error_is_permanent() {
+        let err = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert_eq!(classify_error(&err), ErrorClass::Permanent);
+    }
+
+    #[test]
+    fn transient_io_error_nested_in_source_chain_is_still_transient() {
+        let err = Wrapped(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert_eq!(classify_error(&err), ErrorClass::Transient);
+    }
+
+    #[test]
+    fn error_with_no_io_error_in_chain_is_permanent() {
+        assert_eq!(classify_error(&NoSource), ErrorClass::Permanent);
+    }
+
+    #[tokio::test]
+    async fn with_retry_returns_immediately_on_success() {
+        let config = RetryConfig::default();
+        let attempts = Arc::new(AtomicU32::new(0));
+        let counted = attempts.clone();
+
+        let result: EtlResult<&'static str> = with_retry(&config, move || {
+            let attempts = counted.clone();
+            async move {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Ok("ok")
+            }
+        })
+        .await;
+
+        assert_eq!(result.unwrap(), "ok");
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn with_retry_does_not_retry_permanent_errors() {
+        let config = RetryConfig::default();
+        let attempts = Arc::new(AtomicU32::new(0));
+        let counted = attempts.clone();
+
+        let result: EtlResult<()> = with_retry(&config, move || {
+            let attempts = counted.clone();
+            async move {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err((etl::error::ErrorKind::Unknown, "boom", "permission denied").into())
+            }
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,308 @@
+//! Per-table micro-batching so high-frequency small CDC batches don't each
+//! cost their own `insert_rows` round trip.
+//!
+//! `write_table_rows`/`write_events` used to open/reuse a channel and
+//! immediately flush straight to Snowpipe on every call. Instead, converted
+//! JSON rows are handed to a per-`TableId` `mpsc` channel; a dedicated
+//! background task drains it and only calls `insert_rows` once a
+//! configurable row-count threshold or flush interval is hit, whichever
+//! comes first - the same shape as a bulk-loader streaming JSONL through a
+//! channel to a writer thread.
+
+use super::client::SnowpipeClient;
+use super::client_pool::ClientPool;
+use super::merge::{self, DeleteMode};
+use etl::types::TableId;
+use serde_json::Value;
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// Row-count / time thresholds controlling when a table's buffer flushes.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchConfig {
+    pub max_rows: usize,
+    pub flush_interval: Duration,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_rows: 500,
+            flush_interval: Duration::from_millis(1000),
+        }
+    }
+}
+
+impl BatchConfig {
+    /// Reads `SNOWFLAKE_BATCH_MAX_ROWS` and `SNOWFLAKE_BATCH_FLUSH_INTERVAL_MS`,
+    /// falling back to [`BatchConfig::default`] for anything unset/unparseable.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let max_rows = std::env::var("SNOWFLAKE_BATCH_MAX_ROWS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default.max_rows);
+        let flush_interval = std::env::var("SNOWFLAKE_BATCH_FLUSH_INTERVAL_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(default.flush_interval);
+        Self { max_rows, flush_interval }
+    }
+}
+
+/// MERGE materialization parameters a table's worker needs to re-issue the
+/// `MERGE INTO` after each flush. Sent alongside each batch of rows rather
+/// than fixed at worker-spawn time, since whichever call reaches a table
+/// first (often an initial snapshot copy passing `None`) shouldn't decide
+/// the job for every later batch.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct MergeJob {
+    pub final_table: String,
+    pub pk_columns: Vec<String>,
+    pub column_names: Vec<String>,
+    pub delete_mode: DeleteMode,
+}
+
+/// Owns one background flush task per `TableId`. Each task is handed its
+/// table's own dedicated client from `client_pool`, so distinct tables
+/// flush through distinct connections instead of contending on one shared
+/// lock - `client_pool`'s concurrency limit still caps how many of those
+/// flushes hit Snowpipe at the same instant. `current_token` stays shared
+/// so a flush's resulting token is immediately visible to the next
+/// `write_table_rows`/`write_events` call for that table.
+#[derive(Debug, Clone)]
+pub struct BatchManager {
+    client_pool: Arc<ClientPool>,
+    current_token: Arc<Mutex<HashMap<TableId, String>>>,
+    pg_pool: Pool<Postgres>,
+    config: BatchConfig,
+    senders: Arc<Mutex<HashMap<TableId, mpsc::Sender<(Vec<Value>, Option<MergeJob>)>>>>,
+    handles: Arc<Mutex<HashMap<TableId, JoinHandle<()>>>>,
+}
+
+impl BatchManager {
+    pub fn new(
+        client_pool: Arc<ClientPool>,
+        current_token: Arc<Mutex<HashMap<TableId, String>>>,
+        pg_pool: Pool<Postgres>,
+        config: BatchConfig,
+    ) -> Self {
+        Self {
+            client_pool,
+            current_token,
+            pg_pool,
+            config,
+            senders: Arc::new(Mutex::new(HashMap::new())),
+            handles: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Buffers `rows` for `table_id`, spawning its worker task on first use.
+    /// Returns once the rows are handed off to the buffer, not once they've
+    /// reached Snowflake.
+    pub async fn enqueue(
+        &self,
+        table_id: TableId,
+        table_name: String,
+        merge: Option<MergeJob>,
+        rows: Vec<Value>,
+    ) -> anyhow::Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+
+        let mut senders = self.senders.lock().await;
+        let sender = match senders.get(&table_id) {
+            Some(sender) => sender.clone(),
+            None => {
+                let (tx, rx) = mpsc::channel(self.config.max_rows.max(1) * 4);
+                let handle = spawn_worker(
+                    self.client_pool.client_for(table_id).await?,
+                    self.client_pool.clone(),
+                    self.current_token.clone(),
+                    self.pg_pool.clone(),
+                    self.config,
+                    table_id,
+                    table_name,
+                    rx,
+                );
+                self.handles.lock().await.insert(table_id, handle);
+                senders.insert(table_id, tx.clone());
+                tx
+            }
+        };
+        drop(senders);
+
+        sender
+            .send((rows, merge))
+            .await
+            .map_err(|e| anyhow::anyhow!("Batch worker for table {} is gone: {}", table_id, e))
+    }
+
+    /// Closes every per-table channel so its worker flushes whatever is
+    /// buffered and exits, then waits for all of them to finish. Call this
+    /// before dropping the destination so no buffered rows are lost.
+    pub async fn flush_all(&self) {
+        let senders: Vec<_> = self.senders.lock().await.drain().collect();
+        drop(senders); // closing the sender side signals the worker to drain and exit
+
+        let handles: Vec<_> = self.handles.lock().await.drain().collect();
+        for (table_id, handle) in handles {
+            if let Err(e) = handle.await {
+                error!("Batch worker for table {} panicked during flush: {}", table_id, e);
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn spawn_worker(
+    client: Arc<Mutex<SnowpipeClient>>,
+    client_pool: Arc<ClientPool>,
+    current_token: Arc<Mutex<HashMap<TableId, String>>>,
+    pg_pool: Pool<Postgres>,
+    config: BatchConfig,
+    table_id: TableId,
+    table_name: String,
+    mut rx: mpsc::Receiver<(Vec<Value>, Option<MergeJob>)>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut buffer: Vec<Value> = Vec::new();
+        // A table's worker is spawned by whichever call (write_table_rows or
+        // write_events) touches it first, so the MergeJob can't be fixed at
+        // spawn time - an initial snapshot copy enqueues `None` and later CDC
+        // batches for the same table would never get MERGEd. Track the most
+        // recent non-`None` job seen instead and reuse it for every flush.
+        let mut current_merge: Option<MergeJob> = None;
+        let mut interval = tokio::time::interval(config.flush_interval);
+        interval.tick().await; // first tick fires immediately, skip it
+
+        loop {
+            tokio::select! {
+                received = rx.recv() => {
+                    match received {
+                        Some((rows, merge)) => {
+                            if merge.is_some() {
+                                current_merge = merge;
+                            }
+                            buffer.extend(rows);
+                            if buffer.len() >= config.max_rows {
+                                flush(&client, &client_pool, &current_token, &pg_pool, &table_id, &table_name, &current_merge, &mut buffer).await;
+                            }
+                        }
+                        None => {
+                            flush(&client, &client_pool, &current_token, &pg_pool, &table_id, &table_name, &current_merge, &mut buffer).await;
+                            break;
+                        }
+                    }
+                }
+                _ = interval.tick() => {
+                    if !buffer.is_empty() {
+                        flush(&client, &client_pool, &current_token, &pg_pool, &table_id, &table_name, &current_merge, &mut buffer).await;
+                    }
+                }
+            }
+        }
+    })
+}
+
+async fn flush(
+    client: &Arc<Mutex<SnowpipeClient>>,
+    client_pool: &Arc<ClientPool>,
+    current_token: &Arc<Mutex<HashMap<TableId, String>>>,
+    pg_pool: &Pool<Postgres>,
+    table_id: &TableId,
+    table_name: &str,
+    merge: &Option<MergeJob>,
+    buffer: &mut Vec<Value>,
+) {
+    let rows: Vec<Value> = buffer.drain(..).collect();
+    // `write_events` already dedupes a single call's rows per PK, but this
+    // buffer can hold rows coalesced from more than one `write_events` call
+    // (that's the whole point of micro-batching) - the same PK touched by
+    // two separate calls inside one flush window would otherwise reach the
+    // staging table as two rows and make the MERGE below fail with
+    // "duplicate row detected during DML statement". Re-dedupe the fully
+    // drained buffer right before it's staged so only the last row per PK
+    // in the whole flush survives.
+    let rows = match merge {
+        Some(job) => merge::dedupe_last_per_pk(rows, &job.pk_columns),
+        None => rows,
+    };
+    info!("Flushing {} buffered row(s) for table {}", rows.len(), table_name);
+
+    let mut client_guard = client.lock().await;
+
+    // Bounds how many tables' flushes are hitting Snowpipe at the same
+    // instant, regardless of how many per-table clients exist; held across
+    // the `open_channel`/`insert_rows`/MERGE awaits below and released when
+    // this flush is done.
+    let _permit = client_pool.acquire().await;
+
+    // Read the token and release `current_token` before the `open_channel`/
+    // `insert_rows` awaits below - holding a map-wide lock across a network
+    // round trip would serialize every table's flush through one mutex,
+    // which is exactly the head-of-line blocking the per-table `ClientPool`
+    // exists to remove.
+    let mut token = {
+        let tokens = current_token.lock().await;
+        tokens.get(table_id).cloned().unwrap_or_default()
+    };
+
+    if token.is_empty() {
+        token = match client_guard.open_channel(table_name, "default").await {
+            Ok(t) => t,
+            Err(e) => {
+                error!("Open channel failed while flushing {}, queuing to retry queue: {}", table_name, e);
+                drop(client_guard);
+                requeue(pg_pool, *table_id, table_name, rows, merge.clone()).await;
+                return;
+            }
+        };
+    }
+
+    match client_guard.insert_rows(table_name, "default", rows.clone(), Some(token.clone())).await {
+        Ok(next_token) => {
+            current_token.lock().await.insert(*table_id, next_token);
+            info!("Flushed {} row(s) into {}", rows.len(), table_name);
+
+            if let Some(job) = merge {
+                if let Err(e) = merge::merge_into_final(
+                    &client_guard,
+                    table_name,
+                    &job.final_table,
+                    &job.pk_columns,
+                    &job.column_names,
+                    job.delete_mode,
+                )
+                .await
+                {
+                    error!("MERGE into {} failed after flush: {}", job.final_table, e);
+                }
+            }
+            drop(client_guard);
+        }
+        Err(e) => {
+            drop(client_guard);
+            error!("Insert rows failed while flushing {}, queuing to retry queue: {}", table_name, e);
+            requeue(pg_pool, *table_id, table_name, rows, merge.clone()).await;
+        }
+    }
+}
+
+/// Persists a failed flush to the durable retry queue, carrying `merge`
+/// along so a later successful replay can re-issue the same MERGE this
+/// flush would have - without it, a batch that lands in the retry queue for
+/// a MERGE-mode table gets its rows staged but never merged into the final
+/// mirror, with nothing surfacing the gap.
+async fn requeue(pg_pool: &Pool<Postgres>, table_id: TableId, table_name: &str, rows: Vec<Value>, merge: Option<MergeJob>) {
+    if let Err(e) = super::retry_queue::enqueue(pg_pool, table_id, table_name, rows, merge).await {
+        warn!("Failed to queue flush failure for {} to retry queue, rows dropped: {}", table_name, e);
+    }
+}
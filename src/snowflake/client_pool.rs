@@ -0,0 +1,89 @@
+//! Per-`TableId` `SnowpipeClient` connections.
+//!
+//! Previously every table shared a single `Arc<Mutex<SnowpipeClient>>`, so
+//! `write_events`' per-table loop (and the batcher's per-table flush tasks
+//! after it) all contended on the same lock - only one table's batch could
+//! be in flight at a time no matter how many distinct Snowpipe channels
+//! were involved. An earlier version of this pool hashed each table onto a
+//! fixed slot in a `size`-sized `Vec`, but two tables that collided on the
+//! same slot were back to serializing on one client and sharing its
+//! channel-token namespace - the same problem under a different name. Each
+//! table instead gets its own `SnowpipeClient`, created lazily the first
+//! time it's touched and cached for the life of the process, the same
+//! lazy-per-table pattern `BatchManager` already uses for its flush workers.
+//!
+//! Giving every table its own connection removes head-of-line blocking, but
+//! nothing then stops N tables from having N Snowpipe requests in flight at
+//! once. `concurrency` is the bounded, buffered layer fronting the pool that
+//! keeps that in check: callers `acquire` a permit for the duration of the
+//! actual network round trip (not for the client's whole lifetime), so at
+//! most `size` calls run concurrently regardless of how many tables exist.
+
+use super::client::SnowpipeClient;
+use crate::config::SnowflakeConfig;
+use etl::types::TableId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{Mutex, OwnedSemaphorePermit, Semaphore};
+
+/// Default cap on concurrent in-flight Snowpipe calls across every table's
+/// client, and the initial capacity hint for the client map.
+const DEFAULT_POOL_SIZE: usize = 4;
+
+pub struct ClientPool {
+    config: SnowflakeConfig,
+    clients: Mutex<HashMap<TableId, Arc<Mutex<SnowpipeClient>>>>,
+    /// Bounds how many Snowpipe network calls (`open_channel`/`insert_rows`/
+    /// `execute_sql`) run concurrently across every table's client.
+    concurrency: Arc<Semaphore>,
+}
+
+impl ClientPool {
+    /// `size` both sizes the initial client map and caps concurrent
+    /// in-flight Snowpipe calls via `concurrency` (see module doc).
+    pub fn new(config: SnowflakeConfig, size: usize) -> anyhow::Result<Self> {
+        Ok(Self {
+            config,
+            clients: Mutex::new(HashMap::with_capacity(size.max(1))),
+            concurrency: Arc::new(Semaphore::new(size.max(1))),
+        })
+    }
+
+    /// Reads `SNOWFLAKE_MAX_CONCURRENT_CHANNELS`, falling back to
+    /// [`DEFAULT_POOL_SIZE`] if unset/unparseable.
+    pub fn size_from_env() -> usize {
+        std::env::var("SNOWFLAKE_MAX_CONCURRENT_CHANNELS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_POOL_SIZE)
+    }
+
+    /// The dedicated client for `table_id`, created on first use. Stable
+    /// for the table's lifetime so its sequence of `open_channel`/
+    /// `insert_rows` calls always goes through the same connection, and
+    /// never shared with another table's channel token.
+    pub async fn client_for(&self, table_id: TableId) -> anyhow::Result<Arc<Mutex<SnowpipeClient>>> {
+        let mut clients = self.clients.lock().await;
+        if let Some(client) = clients.get(&table_id) {
+            return Ok(client.clone());
+        }
+
+        let client = SnowpipeClient::new(self.config.clone())
+            .map(|c| Arc::new(Mutex::new(c)))
+            .map_err(|e| anyhow::anyhow!("Failed to init Snowpipe client for table {}: {}", table_id, e))?;
+        clients.insert(table_id, client.clone());
+        Ok(client)
+    }
+
+    /// Acquires a permit bounding concurrent Snowpipe calls to `size`. Hold
+    /// the returned permit only across the actual `open_channel`/
+    /// `insert_rows`/`execute_sql` round trip, never across the client's
+    /// whole lifetime - that would defeat the per-table pool above.
+    pub async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.concurrency
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("ClientPool's semaphore is never closed")
+    }
+}
@@ -0,0 +1,221 @@
+//! Schema-aware auto-DDL for `LANDING_*` tables.
+//!
+//! `resolve_table_name`/`resolve_column_names` assume the target table
+//! already exists in Snowflake and let every value pass through
+//! `ValueEncoder` as a best-effort JSON representation. This module
+//! reads the source column types out of `information_schema.columns`, maps
+//! each one to a concrete Snowflake type, and issues
+//! `CREATE TABLE IF NOT EXISTS` so the landing table is typed instead of
+//! inferred by Snowpipe on first insert.
+//!
+//! DDL/DML statements (here and in `merge`) go through `SnowpipeClient::execute_sql`,
+//! a plain-SQL counterpart to the streaming-ingest `open_channel`/`insert_rows`
+//! pair `SnowpipeClient` already exposes; `client.rs` isn't part of this
+//! source tree, so its signature isn't something this module can verify,
+//! only assume by convention.
+
+use super::client::SnowpipeClient;
+use sqlx::{Pool, Postgres};
+use tracing::{error, info};
+
+/// A single resolved column: its Snowflake-side name/type/nullability.
+#[derive(Debug, Clone)]
+pub struct ColumnSchema {
+    pub name: String,
+    pub sf_type: String,
+    pub nullable: bool,
+}
+
+/// Reads `data_type`/`udt_name`/`is_nullable`/`numeric_precision`/`numeric_scale`
+/// for every column of `table_id` and maps each to a Snowflake type. Returns
+/// an empty vec (caller falls back to untyped behavior) if the table can't
+/// be introspected.
+pub async fn resolve_column_schema(pg_pool: &Pool<Postgres>, table_id: u32) -> Vec<ColumnSchema> {
+    let query = r#"
+        SELECT column_name, data_type, udt_name, is_nullable, numeric_precision, numeric_scale
+        FROM information_schema.columns
+        WHERE table_schema = (SELECT nspname FROM pg_namespace WHERE oid = (SELECT relnamespace FROM pg_class WHERE oid = $1))
+          AND table_name = (SELECT relname FROM pg_class WHERE oid = $1)
+        ORDER BY ordinal_position
+    "#;
+
+    let rows: Vec<(String, String, String, String, Option<i32>, Option<i32>)> = sqlx::query_as(query)
+        .bind(table_id as i32)
+        .fetch_all(pg_pool)
+        .await
+        .unwrap_or_else(|e| {
+            error!("Failed to query column schema for TableId {}: {}", table_id, e);
+            vec![]
+        });
+
+    rows.into_iter()
+        .map(|(name, data_type, udt_name, is_nullable, precision, scale)| ColumnSchema {
+            name: name.to_uppercase(),
+            sf_type: pg_type_to_snowflake_type(&data_type, &udt_name, precision, scale),
+            nullable: is_nullable == "YES",
+        })
+        .collect()
+}
+
+/// Maps a Postgres column type to a concrete Snowflake column type.
+/// `udt_name` is used over `data_type` since it gives the underlying type
+/// name (`int4`, `timestamptz`, `_int4` for an `int4[]`) rather than the
+/// SQL-standard alias `information_schema.columns.data_type` reports.
+fn pg_type_to_snowflake_type(data_type: &str, udt_name: &str, precision: Option<i32>, scale: Option<i32>) -> String {
+    if data_type == "ARRAY" {
+        return "ARRAY".to_string();
+    }
+
+    match udt_name.trim_start_matches('_') {
+        "int2" => "NUMBER(5,0)".to_string(),
+        "int4" => "NUMBER(10,0)".to_string(),
+        "int8" => "NUMBER(19,0)".to_string(),
+        "numeric" => {
+            let p = precision.unwrap_or(38);
+            let s = scale.unwrap_or(0);
+            format!("NUMBER({p},{s})")
+        }
+        "float4" | "float8" => "FLOAT".to_string(),
+        "bool" => "BOOLEAN".to_string(),
+        "bytea" => "BINARY".to_string(),
+        "jsonb" | "json" => "VARIANT".to_string(),
+        "uuid" => "VARCHAR(36)".to_string(),
+        "date" => "DATE".to_string(),
+        "time" | "timetz" => "TIME".to_string(),
+        "timestamp" => "TIMESTAMP_NTZ".to_string(),
+        "timestamptz" => "TIMESTAMP_TZ".to_string(),
+        _ => "VARCHAR".to_string(),
+    }
+}
+
+/// Builds and issues `CREATE TABLE IF NOT EXISTS <table_name> (...)`,
+/// including the synthetic `OPERATION`/`SYNC_TIMESTAMP` columns every
+/// landing table carries alongside the source columns.
+pub async fn ensure_table(client: &SnowpipeClient, table_name: &str, columns: &[ColumnSchema]) -> anyhow::Result<()> {
+    if columns.is_empty() {
+        info!("No column schema resolved for {}, skipping auto-DDL", table_name);
+        return Ok(());
+    }
+
+    let mut column_defs: Vec<String> = columns
+        .iter()
+        .map(|c| {
+            let null_clause = if c.nullable { "" } else { " NOT NULL" };
+            format!("{} {}{}", c.name, c.sf_type, null_clause)
+        })
+        .collect();
+    column_defs.push("OPERATION STRING".to_string());
+    column_defs.push("SYNC_TIMESTAMP TIMESTAMP_TZ".to_string());
+
+    let sql = format!(
+        "CREATE TABLE IF NOT EXISTS {} ({})",
+        table_name,
+        column_defs.join(", ")
+    );
+
+    info!("Ensuring landing table {}: {}", table_name, sql);
+    client
+        .execute_sql(&sql)
+        .await
+        .map_err(|e| anyhow::anyhow!("CREATE TABLE IF NOT EXISTS {} failed: {}", table_name, e))?;
+
+    Ok(())
+}
+
+/// Builds and issues `CREATE TABLE IF NOT EXISTS <final_table> (...)` for
+/// the live mirror a MERGE materializes into: the source columns (no
+/// `OPERATION`, since the final table reflects current state rather than a
+/// change log), `SYNC_TIMESTAMP`, a `PRIMARY KEY` over `pk_columns`, and -
+/// only under soft-delete - the `_DELETED` tombstone flag `merge_into_final`
+/// sets instead of actually deleting the row. Without this, the first
+/// `MERGE INTO <final_table>` targets a table (and, in soft-delete mode, a
+/// column) that was never created.
+pub async fn ensure_final_table(
+    client: &SnowpipeClient,
+    final_table: &str,
+    columns: &[ColumnSchema],
+    pk_columns: &[String],
+    soft_delete: bool,
+) -> anyhow::Result<()> {
+    if columns.is_empty() || pk_columns.is_empty() {
+        info!("No column schema/primary key resolved for {}, skipping final table auto-DDL", final_table);
+        return Ok(());
+    }
+
+    let pk_upper: Vec<String> = pk_columns.iter().map(|c| c.to_uppercase()).collect();
+
+    let mut column_defs: Vec<String> = columns
+        .iter()
+        .map(|c| {
+            let null_clause = if c.nullable { "" } else { " NOT NULL" };
+            format!("{} {}{}", c.name, c.sf_type, null_clause)
+        })
+        .collect();
+    column_defs.push("SYNC_TIMESTAMP TIMESTAMP_TZ".to_string());
+    if soft_delete {
+        column_defs.push("_DELETED BOOLEAN DEFAULT FALSE".to_string());
+    }
+    column_defs.push(format!("PRIMARY KEY ({})", pk_upper.join(", ")));
+
+    let sql = format!(
+        "CREATE TABLE IF NOT EXISTS {} ({})",
+        final_table,
+        column_defs.join(", ")
+    );
+
+    info!("Ensuring final mirror table {}: {}", final_table, sql);
+    client
+        .execute_sql(&sql)
+        .await
+        .map_err(|e| anyhow::anyhow!("CREATE TABLE IF NOT EXISTS {} failed: {}", final_table, e))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_integer_and_numeric_types() {
+        assert_eq!(pg_type_to_snowflake_type("smallint", "int2", None, None), "NUMBER(5,0)");
+        assert_eq!(pg_type_to_snowflake_type("integer", "int4", None, None), "NUMBER(10,0)");
+        assert_eq!(pg_type_to_snowflake_type("bigint", "int8", None, None), "NUMBER(19,0)");
+        assert_eq!(pg_type_to_snowflake_type("numeric", "numeric", Some(12), Some(4)), "NUMBER(12,4)");
+    }
+
+    #[test]
+    fn numeric_falls_back_to_default_precision_and_scale() {
+        assert_eq!(pg_type_to_snowflake_type("numeric", "numeric", None, None), "NUMBER(38,0)");
+    }
+
+    #[test]
+    fn maps_float_bool_binary_and_json_types() {
+        assert_eq!(pg_type_to_snowflake_type("real", "float4", None, None), "FLOAT");
+        assert_eq!(pg_type_to_snowflake_type("double precision", "float8", None, None), "FLOAT");
+        assert_eq!(pg_type_to_snowflake_type("boolean", "bool", None, None), "BOOLEAN");
+        assert_eq!(pg_type_to_snowflake_type("bytea", "bytea", None, None), "BINARY");
+        assert_eq!(pg_type_to_snowflake_type("jsonb", "jsonb", None, None), "VARIANT");
+    }
+
+    #[test]
+    fn maps_date_and_time_types() {
+        assert_eq!(pg_type_to_snowflake_type("uuid", "uuid", None, None), "VARCHAR(36)");
+        assert_eq!(pg_type_to_snowflake_type("date", "date", None, None), "DATE");
+        assert_eq!(pg_type_to_snowflake_type("time without time zone", "time", None, None), "TIME");
+        assert_eq!(pg_type_to_snowflake_type("time with time zone", "timetz", None, None), "TIME");
+        assert_eq!(pg_type_to_snowflake_type("timestamp without time zone", "timestamp", None, None), "TIMESTAMP_NTZ");
+        assert_eq!(pg_type_to_snowflake_type("timestamp with time zone", "timestamptz", None, None), "TIMESTAMP_TZ");
+    }
+
+    #[test]
+    fn array_data_type_short_circuits_regardless_of_udt_name() {
+        assert_eq!(pg_type_to_snowflake_type("ARRAY", "_int4", None, None), "ARRAY");
+        assert_eq!(pg_type_to_snowflake_type("ARRAY", "_text", None, None), "ARRAY");
+    }
+
+    #[test]
+    fn unknown_udt_name_falls_back_to_varchar() {
+        assert_eq!(pg_type_to_snowflake_type("USER-DEFINED", "some_enum", None, None), "VARCHAR");
+    }
+}
@@ -1,9 +1,14 @@
+use super::batcher::{BatchConfig, BatchManager, MergeJob};
 use super::client::SnowpipeClient;
+use super::client_pool::ClientPool;
+use super::ddl::{self, ColumnSchema};
+use super::merge::{self, DeleteMode};
+use super::value_encoder::ValueEncoder;
 use crate::config::SnowflakeConfig;
 use etl::destination::Destination;
 use etl::error::{ErrorKind, EtlResult};
 use etl::etl_error;
-use etl::types::{Cell, Event, TableId, TableRow};
+use etl::types::{Event, TableId, TableRow};
 use serde_json::{Value, json};
 use sqlx::{Pool, Postgres};
 use std::collections::HashMap;
@@ -13,76 +18,49 @@ use tracing::{info, warn, error};
 
 #[derive(Debug, Clone)]
 pub struct SnowflakeDestination {
-    client: Arc<Mutex<SnowpipeClient>>,
+    /// Per-table Snowpipe connections fronted by a bounded concurrency
+    /// limit, so distinct tables flush concurrently without contending on a
+    /// single shared connection, but without N tables opening N simultaneous
+    /// requests either (see `client_pool` module doc).
+    client_pool: Arc<ClientPool>,
     current_token: Arc<Mutex<HashMap<TableId, String>>>,
     pg_pool: Pool<Postgres>,
     table_cache: Arc<Mutex<HashMap<TableId, String>>>,
     column_cache: Arc<Mutex<HashMap<TableId, Vec<String>>>>,
+    /// Resolved Snowflake column types, keyed alongside `column_cache`.
+    /// Populated the first time a table's landing table is auto-DDL'd.
+    column_type_cache: Arc<Mutex<HashMap<TableId, Vec<ColumnSchema>>>>,
+    /// Primary key column names per table, resolved lazily like
+    /// `column_cache`. Empty means the table has no PK (or it hasn't been
+    /// resolved yet and resolution found nothing), which forces a fallback
+    /// to the append-only path.
+    primary_key_cache: Arc<Mutex<HashMap<TableId, Vec<String>>>>,
+    /// Append-only (default) vs MERGE materialization of a live mirror
+    /// table keyed by primary key.
+    materialization_mode: MaterializationMode,
+    delete_mode: DeleteMode,
+    value_encoder: ValueEncoder,
+    /// Per-table micro-batching so small, frequent CDC batches don't each
+    /// pay for their own `insert_rows` round trip.
+    batcher: BatchManager,
 }
 
-// Helper: Convert ETL Cell to JSON Value
-fn cell_to_json_value(cell: &Cell) -> Value {
-    match cell {
-        Cell::Null => Value::Null,
-        Cell::Bool(v) => json!(v),
-        Cell::String(v) => json!(v),
-        Cell::I16(v) => json!(v),
-        Cell::I32(v) => json!(v),
-        Cell::I64(v) => json!(v),
-        Cell::F32(v) => json!(v),
-        Cell::F64(v) => json!(v),
-        Cell::Bytes(v) => json!(format!("<bytes len={}>", v.len())),
-        Cell::Json(v) => v.clone(),
-        Cell::Numeric(v) => json!(v.to_string()),
-        Cell::Uuid(v) => json!(v.to_string()),  // Handle UUID properly
-        Cell::Array(v) => match v {
-            etl::types::ArrayCell::Bool(list) => json!(list),
-            etl::types::ArrayCell::I16(list) => json!(list),
-            etl::types::ArrayCell::I32(list) => json!(list),
-            etl::types::ArrayCell::I64(list) => json!(list),
-            etl::types::ArrayCell::F32(list) => json!(list),
-            etl::types::ArrayCell::F64(list) => json!(list),
-            etl::types::ArrayCell::String(list) => json!(list),
-            etl::types::ArrayCell::Numeric(list) => json!(
-                list.iter()
-                    .map(|opt| opt.as_ref().map(|n| n.to_string()))
-                    .collect::<Vec<_>>()
-            ),
-            etl::types::ArrayCell::Date(list) => json!(
-                list.iter()
-                    .map(|opt| opt.as_ref().map(|d| d.to_string()))
-                    .collect::<Vec<_>>()
-            ),
-            etl::types::ArrayCell::TimestampTz(list) => json!(
-                list.iter()
-                    .map(|opt| opt.as_ref().map(|t| t.to_rfc3339()))
-                    .collect::<Vec<_>>()
-            ),
-            etl::types::ArrayCell::Uuid(list) => json!(
-                list.iter()
-                    .map(|opt| opt.as_ref().map(|u| u.to_string()))
-                    .collect::<Vec<_>>()
-            ),
-            _ => json!(format!("{:?}", v)),
-        },
-        Cell::Date(v) => json!(v.to_string()),
-        Cell::Time(v) => json!(v.to_string()),
-        Cell::Timestamp(v) => json!(v.to_string()),  // NaiveDateTime uses to_string()
-        Cell::TimestampTz(v) => json!(v.to_rfc3339()),
-        _ => json!(format!("{:?}", cell)),
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaterializationMode {
+    AppendOnly,
+    Merge,
 }
 
 // Helper: Convert TableRow to JSON object with column names
-fn row_to_json_object(row: &TableRow, column_names: &[String], operation: &str) -> Value {
+fn row_to_json_object(row: &TableRow, column_names: &[String], operation: &str, encoder: &ValueEncoder) -> Value {
     let mut obj = serde_json::Map::new();
-    
+
     // Add column values - convert to uppercase for Snowflake
     for (i, cell) in row.values.iter().enumerate() {
         let col_name = column_names.get(i)
             .map(|s| s.to_uppercase())
             .unwrap_or_else(|| format!("COL_{}", i));
-        obj.insert(col_name, cell_to_json_value(cell));
+        obj.insert(col_name, encoder.cell_to_json_value(cell));
     }
     
     // Add operation column (uppercase for Snowflake)
@@ -95,20 +73,55 @@ fn row_to_json_object(row: &TableRow, column_names: &[String], operation: &str)
 }
 
 impl SnowflakeDestination {
-    pub fn new(config: SnowflakeConfig, pg_pool: Pool<Postgres>) -> EtlResult<Self> {
-        // Init client (akan hitung fingerprint di sini)
-        let client = SnowpipeClient::new(config)
-            .map_err(|e| etl_error!(ErrorKind::Unknown, "Client init error: {}", e))?;
+    pub async fn new(config: SnowflakeConfig, pg_pool: Pool<Postgres>) -> EtlResult<Self> {
+        let materialization_mode = if config.merge_materialization {
+            MaterializationMode::Merge
+        } else {
+            MaterializationMode::AppendOnly
+        };
+        let delete_mode = if config.soft_delete {
+            DeleteMode::Soft
+        } else {
+            DeleteMode::Hard
+        };
+
+        // Init the pool; each table's client is created lazily in `client_for`
+        let client_pool = Arc::new(
+            ClientPool::new(config, ClientPool::size_from_env())
+                .map_err(|e| etl_error!(ErrorKind::Unknown, "Client init error: {}", e))?,
+        );
+
+        super::retry_queue::ensure_schema(&pg_pool)
+            .await
+            .map_err(|e| etl_error!(ErrorKind::Unknown, "Failed to prepare retry queue schema: {}", e))?;
+
+        let current_token = Arc::new(Mutex::new(HashMap::new()));
+        super::retry_queue::spawn_worker(pg_pool.clone(), client_pool.clone(), current_token.clone());
+
+        let batcher = BatchManager::new(client_pool.clone(), current_token.clone(), pg_pool.clone(), BatchConfig::from_env());
 
         Ok(Self {
-            client: Arc::new(Mutex::new(client)),
-            current_token: Arc::new(Mutex::new(HashMap::new())),
+            client_pool,
+            current_token,
             pg_pool,
             table_cache: Arc::new(Mutex::new(HashMap::new())),
             column_cache: Arc::new(Mutex::new(HashMap::new())),
+            column_type_cache: Arc::new(Mutex::new(HashMap::new())),
+            primary_key_cache: Arc::new(Mutex::new(HashMap::new())),
+            materialization_mode,
+            delete_mode,
+            value_encoder: ValueEncoder::from_env(),
+            batcher,
         })
     }
 
+    /// Drains every per-table buffer, advancing `current_token` for each
+    /// one, and waits for the flushes to complete. Call this before the
+    /// destination is dropped so no buffered rows are lost.
+    pub async fn flush(&self) {
+        self.batcher.flush_all().await;
+    }
+
     async fn resolve_table_name(&self, table_id: TableId) -> String {
         let mut cache = self.table_cache.lock().await;
         if let Some(name) = cache.get(&table_id) {
@@ -182,6 +195,132 @@ impl SnowflakeDestination {
         cache.insert(table_id, column_names.clone());
         column_names
     }
+
+    async fn resolve_column_schema(&self, table_id: TableId) -> Vec<ColumnSchema> {
+        let mut cache = self.column_type_cache.lock().await;
+        if let Some(schema) = cache.get(&table_id) {
+            return schema.clone();
+        }
+
+        let schema = ddl::resolve_column_schema(&self.pg_pool, table_id.0).await;
+        cache.insert(table_id, schema.clone());
+        schema
+    }
+
+    /// Issues `CREATE TABLE IF NOT EXISTS` for the table's landing table (and,
+    /// in MERGE mode, its final mirror table) the first time a channel is
+    /// opened for it, so a fresh table shows up typed instead of whatever
+    /// Snowpipe infers from the first insert.
+    async fn ensure_landing_table(&self, table_id: TableId, table_name: &str, client: &SnowpipeClient) {
+        let schema = self.resolve_column_schema(table_id).await;
+        if let Err(e) = ddl::ensure_table(client, table_name, &schema).await {
+            warn!("Auto-DDL for {} failed, continuing without it: {}", table_name, e);
+        }
+
+        if self.materialization_mode == MaterializationMode::Merge {
+            let pk_columns = self.resolve_primary_key(table_id).await;
+            if !pk_columns.is_empty() {
+                let final_table = Self::final_table_name(table_name);
+                if let Err(e) = ddl::ensure_final_table(
+                    client,
+                    &final_table,
+                    &schema,
+                    &pk_columns,
+                    self.delete_mode == DeleteMode::Soft,
+                )
+                .await
+                {
+                    warn!("Auto-DDL for final mirror table {} failed, continuing without it: {}", final_table, e);
+                }
+            }
+        }
+    }
+
+    /// Ensures the landing table exists and a channel is open for
+    /// `table_id` before any rows for it are buffered. The buffer's flush
+    /// task reuses whatever token this leaves in `current_token`.
+    async fn ensure_channel_open(&self, table_id: TableId, table_name: &str) -> EtlResult<()> {
+        let client = self
+            .client_pool
+            .client_for(table_id)
+            .await
+            .map_err(|e| etl_error!(ErrorKind::Unknown, "Client init error: {}", e))?;
+        let mut client = client.lock().await;
+
+        // Read the token and release `current_token` before the auto-DDL /
+        // `open_channel` awaits below - holding a map-wide lock across a
+        // network round trip would serialize every table's channel-open
+        // through one mutex, which is exactly the head-of-line blocking the
+        // per-table `ClientPool` exists to remove.
+        let existing_token = {
+            let tokens = self.current_token.lock().await;
+            tokens.get(&table_id).cloned()
+        };
+
+        let needs_open = match existing_token.as_deref() {
+            Some(token) => token.is_empty(),
+            None => true,
+        };
+        if needs_open {
+            let _permit = self.client_pool.acquire().await;
+            self.ensure_landing_table(table_id, table_name, &client).await;
+            info!("Opening new channel for table: {}", table_name);
+            let token = client
+                .open_channel(table_name, "default")
+                .await
+                .map_err(|e| {
+                    error!("Open channel failed for {}: {}", table_name, e);
+                    etl_error!(ErrorKind::Unknown, "Open channel failed: {}", e)
+                })?;
+            info!("Channel opened successfully for {}. Token: {}", table_name, token);
+            self.current_token.lock().await.insert(table_id, token);
+        } else {
+            info!("Using existing channel for table: {}. Token: {:?}", table_name, existing_token);
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the table's primary key columns (in ordinal order) via
+    /// `pg_index`/`pg_attribute`. Returns an empty vec if the table has no
+    /// primary key, in which case MERGE materialization isn't possible and
+    /// the caller should fall back to append-only.
+    async fn resolve_primary_key(&self, table_id: TableId) -> Vec<String> {
+        let mut cache = self.primary_key_cache.lock().await;
+        if let Some(columns) = cache.get(&table_id) {
+            return columns.clone();
+        }
+
+        let query = r#"
+            SELECT a.attname
+            FROM pg_index i
+            JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey)
+            WHERE i.indrelid = $1 AND i.indisprimary
+            ORDER BY array_position(i.indkey, a.attnum)
+        "#;
+
+        let rows: Vec<(String,)> = sqlx::query_as(query)
+            .bind(table_id.0 as i32)
+            .fetch_all(&self.pg_pool)
+            .await
+            .unwrap_or_else(|e| {
+                error!("Failed to query primary key for TableId {}: {}", table_id, e);
+                vec![]
+            });
+
+        let pk_columns: Vec<String> = rows.into_iter().map(|(name,)| name).collect();
+        cache.insert(table_id, pk_columns.clone());
+        pk_columns
+    }
+
+    /// The final mirror table a MERGE targets, as opposed to the transient
+    /// `LANDING_*` staging table a batch is streamed into first.
+    fn final_table_name(landing_table: &str) -> String {
+        landing_table
+            .strip_prefix("LANDING_")
+            .map(|name| name.to_string())
+            .unwrap_or_else(|| landing_table.to_string())
+    }
 }
 
 impl Destination for SnowflakeDestination {
@@ -208,48 +347,58 @@ impl Destination for SnowflakeDestination {
         let column_names = self.resolve_column_names(table_id).await;
         info!("Found {} columns for table {}", column_names.len(), table_name);
         
-        let mut client = self.client.lock().await;
-        let mut tokens = self.current_token.lock().await;
+        self.ensure_channel_open(table_id, &table_name).await?;
 
-        let token = tokens.entry(table_id).or_insert_with(String::new);
+        let mut json_rows: Vec<Value> = rows
+            .iter()
+            .map(|r| row_to_json_object(r, &column_names, "C", &self.value_encoder))
+            .collect();
 
-        if token.is_empty() {
-            info!("Opening new channel for table: {}", table_name);
-            *token = client
-                .open_channel(&table_name, "default")
-                .await
-                .map_err(|e| {
-                    error!("Open channel failed for {}: {}", table_name, e);
-                    etl_error!(ErrorKind::Unknown, "Open channel failed: {}", e)
-                })?;
-            info!("Channel opened successfully for {}. Token: {}", table_name, token);
+        info!("Converted {} rows to JSON for table {}", json_rows.len(), table_name);
+
+        // Same PK resolution/dedupe/merge-job plumbing as `write_events` -
+        // this path carries the initial snapshot copy, so in MERGE mode it
+        // has to populate `final_table` too, or the live mirror stays empty
+        // until an unrelated CDC event happens to touch each PK. Without the
+        // same merge job wired up, these batches also never hit
+        // `merge_into_final`'s post-MERGE truncate, so they'd sit in staging
+        // until a later CDC write for the same PK fails with "duplicate row
+        // detected during DML statement" (see merge.rs's `merge_into_final`).
+        let pk_columns = if self.materialization_mode == MaterializationMode::Merge {
+            self.resolve_primary_key(table_id).await
         } else {
-            info!("Using existing channel for table: {}. Token: {}", table_name, token);
+            Vec::new()
+        };
+        let use_merge = self.materialization_mode == MaterializationMode::Merge && !pk_columns.is_empty();
+        if self.materialization_mode == MaterializationMode::Merge && pk_columns.is_empty() {
+            warn!(
+                "Table {} has no primary key; falling back to append-only for this snapshot batch",
+                table_name
+            );
+        }
+
+        if use_merge {
+            json_rows = merge::dedupe_last_per_pk(json_rows, &pk_columns);
         }
 
-        let json_rows: Vec<Value> = rows
-            .iter()
-            .map(|r| row_to_json_object(r, &column_names, "C"))
-            .collect();
-        
-        info!("Converted {} rows to JSON for table {}", json_rows.len(), table_name);
-        
         // Log first row as sample for debugging
         if let Some(first_row) = json_rows.first() {
             info!("Sample JSON row for {}: {}", table_name, serde_json::to_string_pretty(first_row).unwrap_or_default());
         }
 
-        info!("Inserting {} rows into Snowflake table: {}", json_rows.len(), table_name);
-        let next_token = client
-            .insert_rows(&table_name, "default", json_rows, Some(token.clone()))
+        let merge_job = use_merge.then(|| MergeJob {
+            final_table: Self::final_table_name(&table_name),
+            pk_columns: pk_columns.clone(),
+            column_names: column_names.clone(),
+            delete_mode: self.delete_mode,
+        });
+
+        info!("Buffering {} rows for table {}", json_rows.len(), table_name);
+        self.batcher
+            .enqueue(table_id, table_name.clone(), merge_job, json_rows)
             .await
-            .map_err(|e| {
-                error!("Insert rows failed for {}: {}", table_name, e);
-                etl_error!(ErrorKind::Unknown, "Write rows failed: {}", e)
-            })?;
+            .map_err(|e| etl_error!(ErrorKind::Unknown, "Failed to buffer rows for {}: {}", table_name, e))?;
 
-        *token = next_token;
-        info!("Successfully inserted rows into {}. New token: {}", table_name, token);
         Ok(())
     }
 
@@ -281,33 +430,28 @@ impl Destination for SnowflakeDestination {
         
         info!("Events grouped into {} table(s)", events_by_table.len());
 
+        // Resolve and open every table's channel before buffering any of
+        // them. `ensure_channel_open` is the one step in this loop that can
+        // fail on a live Snowflake/network issue - if buffering happened
+        // per table instead, a later table's failure would leave an earlier
+        // table's batch already durably enqueued in the micro-batcher (a
+        // real side effect heading to Snowflake) while this whole call still
+        // reports an error. `Multi` DLQs and later replays every table in a
+        // failed call, so that partial commit would turn into a duplicate
+        // write for the table that actually succeeded. Preparing every
+        // table up front means one table's failure aborts the call before
+        // any table has been buffered.
+        let mut prepared = Vec::new();
         for (table_id, events) in events_by_table {
             info!("Processing {} events for TableId {}", events.len(), table_id);
-            
+
             let table_name = self.resolve_table_name(table_id).await;
             info!("Target table: {}", table_name);
-            
+
             let column_names = self.resolve_column_names(table_id).await;
             info!("Found {} columns for table {}", column_names.len(), table_name);
 
-            let mut client = self.client.lock().await;
-            let mut tokens = self.current_token.lock().await;
-
-            let token = tokens.entry(table_id).or_insert_with(String::new);
-
-            if token.is_empty() {
-                info!("Opening new channel for table: {}", table_name);
-                *token = client
-                    .open_channel(&table_name, "default")
-                    .await
-                    .map_err(|e| {
-                        error!("Open channel failed for {}: {}", table_name, e);
-                        etl_error!(ErrorKind::Unknown, "Open channel failed: {}", e)
-                    })?;
-                info!("Channel opened successfully for {}. Token: {}", table_name, token);
-            } else {
-                info!("Using existing channel for table: {}. Token: {}", table_name, token);
-            }
+            self.ensure_channel_open(table_id, &table_name).await?;
 
             let mut json_rows = Vec::new();
             let mut inserts = 0;
@@ -318,17 +462,17 @@ impl Destination for SnowflakeDestination {
                 let row_obj = match event {
                     Event::Insert(i) => {
                         inserts += 1;
-                        Some(row_to_json_object(&i.table_row, &column_names, "C"))
+                        Some(row_to_json_object(&i.table_row, &column_names, "C", &self.value_encoder))
                     }
                     Event::Update(u) => {
                         updates += 1;
-                        Some(row_to_json_object(&u.table_row, &column_names, "U"))
+                        Some(row_to_json_object(&u.table_row, &column_names, "U", &self.value_encoder))
                     }
                     Event::Delete(d) => {
                         deletes += 1;
                         // For deletes, use old_table_row if available, otherwise empty row
                         if let Some((_, old_row)) = &d.old_table_row {
-                            Some(row_to_json_object(old_row, &column_names, "D"))
+                            Some(row_to_json_object(old_row, &column_names, "D", &self.value_encoder))
                         } else {
                             warn!("Delete event without old_table_row, skipping");
                             None
@@ -342,31 +486,55 @@ impl Destination for SnowflakeDestination {
                 }
             }
             
-            info!("Event breakdown for {}: {} inserts, {} updates, {} deletes", 
+            info!("Event breakdown for {}: {} inserts, {} updates, {} deletes",
                   table_name, inserts, updates, deletes);
+
+            let pk_columns = if self.materialization_mode == MaterializationMode::Merge {
+                self.resolve_primary_key(table_id).await
+            } else {
+                Vec::new()
+            };
+            let use_merge = self.materialization_mode == MaterializationMode::Merge && !pk_columns.is_empty();
+            if self.materialization_mode == MaterializationMode::Merge && pk_columns.is_empty() {
+                warn!(
+                    "Table {} has no primary key; falling back to append-only for this batch",
+                    table_name
+                );
+            }
+
+            if use_merge {
+                json_rows = merge::dedupe_last_per_pk(json_rows, &pk_columns);
+            }
+
             info!("Converted {} events to JSON rows for {}", json_rows.len(), table_name);
-            
+
             // Log first row as sample for debugging
             if let Some(first_row) = json_rows.first() {
                 info!("Sample JSON event for {}: {}", table_name, serde_json::to_string_pretty(first_row).unwrap_or_default());
             }
 
             if !json_rows.is_empty() {
-                info!("Inserting {} rows into Snowflake table: {}", json_rows.len(), table_name);
-                let next_token = client
-                    .insert_rows(&table_name, "default", json_rows, Some(token.clone()))
-                    .await
-                    .map_err(|e| {
-                        error!("Insert events failed for {}: {}", table_name, e);
-                        etl_error!(ErrorKind::Unknown, "Write events failed: {}", e)
-                    })?;
-                *token = next_token;
-                info!("Successfully inserted events into {}. New token: {}", table_name, token);
+                let merge_job = use_merge.then(|| MergeJob {
+                    final_table: Self::final_table_name(&table_name),
+                    pk_columns: pk_columns.clone(),
+                    column_names: column_names.clone(),
+                    delete_mode: self.delete_mode,
+                });
+
+                prepared.push((table_id, table_name, merge_job, json_rows));
             } else {
                 warn!("No JSON rows to insert for table {}", table_name);
             }
         }
 
+        for (table_id, table_name, merge_job, json_rows) in prepared {
+            info!("Buffering {} rows for table {}", json_rows.len(), table_name);
+            self.batcher
+                .enqueue(table_id, table_name.clone(), merge_job, json_rows)
+                .await
+                .map_err(|e| etl_error!(ErrorKind::Unknown, "Failed to buffer events for {}: {}", table_name, e))?;
+        }
+
         Ok(())
     }
 }
@@ -0,0 +1,210 @@
+//! MERGE-based CDC materialization.
+//!
+//! The default append-only mode just streams every change into a
+//! `LANDING_*` table and never reflects current row state. This mode keeps
+//! a live mirror table keyed by primary key up to date by staging a batch
+//! into a transient `LANDING_*` table (same as append-only) and then
+//! issuing a single `MERGE INTO <final> USING <staging> ...` that applies
+//! inserts/updates/deletes in one statement. Issued via `execute_sql`,
+//! same caveat as [`super::ddl`].
+
+use super::client::SnowpipeClient;
+use std::collections::HashMap;
+use tracing::{info, warn};
+
+/// Whether a deleted row is actually removed from the mirror table, or kept
+/// with a `_DELETED` flag set (so downstream consumers can see tombstones).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum DeleteMode {
+    Soft,
+    Hard,
+}
+
+/// Collapses a batch down to the last operation per primary key, since a
+/// single MERGE statement can only apply one action per matched row.
+pub fn dedupe_last_per_pk(rows: Vec<serde_json::Value>, pk_columns: &[String]) -> Vec<serde_json::Value> {
+    let pk_columns_upper: Vec<String> = pk_columns.iter().map(|c| c.to_uppercase()).collect();
+    let mut last_by_pk: HashMap<String, serde_json::Value> = HashMap::new();
+    let mut order: Vec<String> = Vec::new();
+
+    for row in rows {
+        let Some(obj) = row.as_object() else {
+            continue;
+        };
+        let pk_key = pk_columns_upper
+            .iter()
+            .map(|col| obj.get(col).map(|v| v.to_string()).unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join("\u{1}");
+
+        if !last_by_pk.contains_key(&pk_key) {
+            order.push(pk_key.clone());
+        }
+        last_by_pk.insert(pk_key, row);
+    }
+
+    order.into_iter().filter_map(|k| last_by_pk.remove(&k)).collect()
+}
+
+/// Builds and issues the `MERGE INTO <final> USING <staging> ...` statement
+/// that applies a staged batch's inserts/updates/deletes in one shot.
+pub async fn merge_into_final(
+    client: &SnowpipeClient,
+    staging_table: &str,
+    final_table: &str,
+    pk_columns: &[String],
+    column_names: &[String],
+    delete_mode: DeleteMode,
+) -> anyhow::Result<()> {
+    if pk_columns.is_empty() {
+        warn!(
+            "Table {} has no primary key; MERGE materialization is not possible, caller should fall back to append-only",
+            final_table
+        );
+        return Err(anyhow::anyhow!("no primary key available for MERGE materialization"));
+    }
+
+    let pk_upper: Vec<String> = pk_columns.iter().map(|c| c.to_uppercase()).collect();
+    let cols_upper: Vec<String> = column_names.iter().map(|c| c.to_uppercase()).collect();
+    let non_pk_cols: Vec<&String> = cols_upper.iter().filter(|c| !pk_upper.contains(c)).collect();
+
+    let on_clause = pk_upper
+        .iter()
+        .map(|c| format!("final.{c} = staging.{c}"))
+        .collect::<Vec<_>>()
+        .join(" AND ");
+
+    let update_set = non_pk_cols
+        .iter()
+        .map(|c| format!("{c} = staging.{c}"))
+        .chain(std::iter::once("SYNC_TIMESTAMP = staging.SYNC_TIMESTAMP".to_string()))
+        .chain((delete_mode == DeleteMode::Soft).then(|| "_DELETED = FALSE".to_string()))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let insert_cols = cols_upper.join(", ");
+    let insert_values = cols_upper
+        .iter()
+        .map(|c| format!("staging.{c}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let delete_clause = match delete_mode {
+        DeleteMode::Hard => "WHEN MATCHED AND staging.OPERATION = 'D' THEN DELETE".to_string(),
+        DeleteMode::Soft => format!(
+            "WHEN MATCHED AND staging.OPERATION = 'D' THEN UPDATE SET _DELETED = TRUE, SYNC_TIMESTAMP = staging.SYNC_TIMESTAMP"
+        ),
+    };
+
+    let sql = format!(
+        "MERGE INTO {final_table} AS final USING {staging_table} AS staging ON {on_clause} \
+         {delete_clause} \
+         WHEN MATCHED AND staging.OPERATION != 'D' THEN UPDATE SET {update_set} \
+         WHEN NOT MATCHED AND staging.OPERATION != 'D' THEN INSERT ({insert_cols}, SYNC_TIMESTAMP) VALUES ({insert_values}, staging.SYNC_TIMESTAMP)",
+    );
+
+    info!("Issuing MERGE for {}: {}", final_table, sql);
+    client
+        .execute_sql(&sql)
+        .await
+        .map_err(|e| anyhow::anyhow!("MERGE into {} failed: {}", final_table, e))?;
+
+    // `staging_table` is the append-only `LANDING_*` table Snowpipe streams
+    // into, so left alone it keeps every historical batch. A later MERGE
+    // would then see more than one row for any PK touched more than once
+    // and Snowflake rejects that with "duplicate row detected during DML"
+    // (`dedupe_last_per_pk` only dedupes within one in-memory batch, not
+    // across what's already landed). Truncating right after a successful
+    // MERGE keeps the staging table holding only the batch the next MERGE
+    // hasn't consumed yet.
+    client
+        .execute_sql(&format!("TRUNCATE TABLE {}", staging_table))
+        .await
+        .map_err(|e| anyhow::anyhow!("Truncate staging table {} failed after MERGE: {}", staging_table, e))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn pk(col: &str) -> Vec<String> {
+        vec![col.to_string()]
+    }
+
+    #[test]
+    fn keeps_only_the_last_row_per_pk_within_one_call() {
+        let rows = vec![
+            json!({"ID": 1, "OPERATION": "I", "NAME": "first"}),
+            json!({"ID": 2, "OPERATION": "I", "NAME": "other"}),
+            json!({"ID": 1, "OPERATION": "U", "NAME": "second"}),
+            json!({"ID": 1, "OPERATION": "D", "NAME": "second"}),
+        ];
+
+        let deduped = dedupe_last_per_pk(rows, &pk("id"));
+
+        assert_eq!(deduped.len(), 2);
+        assert_eq!(deduped[0]["OPERATION"], "D");
+        assert_eq!(deduped[1]["ID"], 2);
+    }
+
+    #[test]
+    fn preserves_first_seen_order_of_distinct_pks() {
+        let rows = vec![
+            json!({"ID": 3, "OPERATION": "I"}),
+            json!({"ID": 1, "OPERATION": "I"}),
+            json!({"ID": 3, "OPERATION": "U"}),
+            json!({"ID": 2, "OPERATION": "I"}),
+        ];
+
+        let deduped = dedupe_last_per_pk(rows, &pk("id"));
+
+        let ids: Vec<_> = deduped.iter().map(|r| r["ID"].as_i64().unwrap()).collect();
+        assert_eq!(ids, vec![3, 1, 2]);
+    }
+
+    #[test]
+    fn dedupes_across_rows_coalesced_from_two_separate_enqueues() {
+        // Mirrors `batcher::flush` concatenating two `write_events` calls'
+        // rows into one buffer before a single flush re-dedupes the whole
+        // thing (the bug fixed in 718163d): without re-running dedup over
+        // the combined buffer, the same PK updated in both calls would
+        // reach the staging table as two rows.
+        let first_call = vec![json!({"ID": 1, "OPERATION": "I", "NAME": "a"})];
+        let second_call = vec![
+            json!({"ID": 1, "OPERATION": "U", "NAME": "b"}),
+            json!({"ID": 2, "OPERATION": "I", "NAME": "c"}),
+        ];
+
+        let mut buffered = first_call;
+        buffered.extend(second_call);
+        let deduped = dedupe_last_per_pk(buffered, &pk("id"));
+
+        assert_eq!(deduped.len(), 2);
+        assert_eq!(deduped[0]["NAME"], "b");
+    }
+
+    #[test]
+    fn composite_pk_is_keyed_on_every_column() {
+        let rows = vec![
+            json!({"TENANT": 1, "ID": 1, "OPERATION": "I", "NAME": "a"}),
+            json!({"TENANT": 2, "ID": 1, "OPERATION": "I", "NAME": "b"}),
+            json!({"TENANT": 1, "ID": 1, "OPERATION": "U", "NAME": "c"}),
+        ];
+
+        let deduped = dedupe_last_per_pk(rows, &vec!["tenant".to_string(), "id".to_string()]);
+
+        assert_eq!(deduped.len(), 2);
+    }
+
+    #[test]
+    fn non_object_rows_are_dropped() {
+        let rows = vec![json!([1, 2, 3]), json!({"ID": 1, "OPERATION": "I"})];
+
+        let deduped = dedupe_last_per_pk(rows, &pk("id"));
+
+        assert_eq!(deduped.len(), 1);
+    }
+}
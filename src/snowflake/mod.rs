@@ -0,0 +1,10 @@
+mod batcher;
+mod client_pool;
+mod ddl;
+mod destination;
+mod merge;
+mod retry_queue;
+mod value_encoder;
+
+pub use destination::SnowflakeDestination;
+pub use value_encoder::{BinaryEncoding, NumericEncoding, ValueEncoder};
@@ -0,0 +1,329 @@
+//! Durable Postgres-backed retry queue for failed Snowpipe batches.
+//!
+//! `insert_rows`/`open_channel` failures used to just bubble up and lose
+//! the batch on restart. Failed batches are persisted into
+//! `snowflake_retry_queue` (backed by the existing `pg_pool`) instead, and
+//! a background worker drains it with `SELECT ... FOR UPDATE SKIP LOCKED`
+//! so multiple processes can share the queue without double-processing a
+//! row, giving at-least-once delivery across restarts and transient
+//! Snowflake outages. Repeated failures back off exponentially via
+//! `next_attempt_at` before a row is parked as `failed`.
+
+use super::batcher::MergeJob;
+use super::client_pool::ClientPool;
+use super::merge;
+use anyhow::{Context, Result};
+use etl::types::TableId;
+use serde_json::Value;
+use sqlx::{Pool, Postgres};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Stop retrying a row and park it as permanently failed after this many
+/// attempts.
+const MAX_ATTEMPTS: i32 = 10;
+
+/// How often the worker polls for claimable rows.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Backoff base for re-queued rows: `BACKOFF_BASE_SECS * 2^attempts`, capped
+/// at `BACKOFF_MAX_SECS`. Mirrors the shape of [`crate::retry::RetryConfig`]
+/// but is computed in SQL (as an absolute `next_attempt_at`) since the delay
+/// has to survive across worker polls and process restarts.
+const BACKOFF_BASE_SECS: i64 = 5;
+const BACKOFF_MAX_SECS: i64 = 300;
+
+/// A `running` row whose `heartbeat` is older than this is assumed to
+/// belong to a worker that crashed (or was killed) after claiming it but
+/// before finishing, and is reclaimable by another worker.
+const HEARTBEAT_STALE_SECS: i64 = 60;
+
+/// How often a claimed row's heartbeat is renewed while the
+/// `open_channel`/`insert_rows`/MERGE call is in flight, well under
+/// `HEARTBEAT_STALE_SECS` so a legitimately slow (not crashed) call never
+/// makes the row look reclaimable to another worker.
+const HEARTBEAT_RENEW_INTERVAL: Duration = Duration::from_secs(20);
+
+fn backoff_secs(attempts: i32) -> i64 {
+    BACKOFF_BASE_SECS.saturating_mul(1i64 << attempts.min(10)).min(BACKOFF_MAX_SECS)
+}
+
+/// Creates `snowflake_retry_queue` (and its status enum) if they don't
+/// already exist. Safe to call on every startup.
+pub async fn ensure_schema(pool: &Pool<Postgres>) -> Result<()> {
+    sqlx::query(
+        r#"
+        DO $$ BEGIN
+            CREATE TYPE snowflake_retry_status AS ENUM ('new', 'running', 'failed');
+        EXCEPTION WHEN duplicate_object THEN NULL;
+        END $$;
+        "#,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create snowflake_retry_status enum")?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS snowflake_retry_queue (
+            id UUID PRIMARY KEY,
+            table_id INT NOT NULL,
+            channel_name TEXT NOT NULL,
+            payload JSONB NOT NULL,
+            status snowflake_retry_status NOT NULL DEFAULT 'new',
+            attempts INT NOT NULL DEFAULT 0,
+            heartbeat TIMESTAMPTZ,
+            next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+            created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create snowflake_retry_queue table")?;
+
+    Ok(())
+}
+
+/// Payload shape stored in the `payload` JSONB column: the converted JSON
+/// rows plus the channel they were destined for. `merge` carries the same
+/// `MergeJob` the original flush would have re-issued after `insert_rows`,
+/// so a MERGE-mode table's live mirror doesn't go stale just because its
+/// batch had to take the retry-queue detour.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct RetryPayload {
+    channel_name: String,
+    rows: Vec<Value>,
+    merge: Option<MergeJob>,
+}
+
+/// Persists a batch that failed `insert_rows`/`open_channel` so it survives
+/// a restart instead of being dropped.
+pub async fn enqueue(
+    pool: &Pool<Postgres>,
+    table_id: TableId,
+    channel_name: &str,
+    rows: Vec<Value>,
+    merge: Option<MergeJob>,
+) -> Result<()> {
+    let payload = serde_json::to_value(RetryPayload {
+        channel_name: channel_name.to_string(),
+        rows,
+        merge,
+    })
+    .context("Failed to encode retry queue payload")?;
+
+    sqlx::query(
+        "INSERT INTO snowflake_retry_queue (id, table_id, channel_name, payload) VALUES ($1, $2, $3, $4)",
+    )
+    .bind(Uuid::new_v4())
+    .bind(table_id.0 as i32)
+    .bind(channel_name)
+    .bind(payload)
+    .execute(pool)
+    .await
+    .context("Failed to enqueue batch to snowflake_retry_queue")?;
+
+    Ok(())
+}
+
+/// Claims one `new` or stale `running` row, re-attempts the insert, and
+/// either deletes it (success), re-queues with incremented `attempts`
+/// (any failure under the attempt cap), or parks it as `failed`
+/// (attempt cap exceeded).
+async fn process_one(
+    pool: &Pool<Postgres>,
+    client_pool: &Arc<ClientPool>,
+    current_token: &Arc<Mutex<HashMap<TableId, String>>>,
+) -> Result<bool> {
+    let mut tx = pool.begin().await.context("Failed to begin retry queue transaction")?;
+
+    // `FOR UPDATE SKIP LOCKED` only protects a row while this transaction
+    // holds its lock, which ends as soon as the claim below commits. Without
+    // the heartbeat check every `running` row (including ones another
+    // worker is actively processing right now) would be re-selected and
+    // re-run concurrently; gating on a stale heartbeat instead limits
+    // reclaiming to rows whose worker crashed mid-flight.
+    let row: Option<(Uuid, i32, String, Value, i32)> = sqlx::query_as(
+        r#"
+        SELECT id, table_id, channel_name, payload, attempts
+        FROM snowflake_retry_queue
+        WHERE (status = 'new' AND next_attempt_at <= now())
+           OR (status = 'running' AND heartbeat < now() - ($1 || ' seconds')::interval)
+        ORDER BY created_at
+        FOR UPDATE SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .bind(HEARTBEAT_STALE_SECS.to_string())
+    .fetch_optional(&mut *tx)
+    .await
+    .context("Failed to claim row from snowflake_retry_queue")?;
+
+    let Some((id, table_id_raw, channel_name, payload, attempts)) = row else {
+        tx.commit().await.ok();
+        return Ok(false);
+    };
+
+    sqlx::query("UPDATE snowflake_retry_queue SET status = 'running', heartbeat = now() WHERE id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to mark retry queue row running")?;
+    tx.commit().await.context("Failed to commit retry queue claim")?;
+
+    let table_id = TableId(table_id_raw as u32);
+    let parsed: RetryPayload = serde_json::from_value(payload).context("Failed to decode retry queue payload")?;
+
+    // The claim above stamps the heartbeat once, but `HEARTBEAT_STALE_SECS`
+    // is only 60s - a slow-but-healthy Snowflake call that runs longer than
+    // that would otherwise make this row look crashed and reclaimable by
+    // another worker mid-flight, producing a duplicate insert/MERGE. Renew
+    // it on a side task for as long as the call below is running.
+    let heartbeat_pool = pool.clone();
+    let heartbeat_task = tokio::spawn(async move {
+        let mut interval = tokio::time::interval(HEARTBEAT_RENEW_INTERVAL);
+        interval.tick().await; // first tick fires immediately; the claim just stamped it
+        loop {
+            interval.tick().await;
+            if sqlx::query("UPDATE snowflake_retry_queue SET heartbeat = now() WHERE id = $1")
+                .bind(id)
+                .execute(&heartbeat_pool)
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    let client = client_pool
+        .client_for(table_id)
+        .await
+        .context("Failed to init Snowpipe client for retry queue row")?;
+    let mut client_guard = client.lock().await;
+    // Caps concurrent retry-queue replays against Snowpipe alongside the
+    // batcher's own flushes, same as every other `ClientPool` caller.
+    let _permit = client_pool.acquire().await;
+    // Re-open (or reuse) the channel explicitly rather than trusting a
+    // possibly-stale in-memory token, since this row may be replayed well
+    // after the original write attempt.
+    let result = match client_guard.open_channel(&channel_name, "default").await {
+        Ok(token) => client_guard
+            .insert_rows(&channel_name, "default", parsed.rows.clone(), Some(token))
+            .await,
+        Err(e) => Err(e),
+    };
+
+    if let (Ok(_), Some(job)) = (&result, &parsed.merge) {
+        // Same MERGE the original flush would have issued right after
+        // `insert_rows` - skipping it here is exactly how a MERGE-mode
+        // table's final mirror goes silently stale for a replayed batch.
+        if let Err(e) = merge::merge_into_final(
+            &client_guard,
+            &channel_name,
+            &job.final_table,
+            &job.pk_columns,
+            &job.column_names,
+            job.delete_mode,
+        )
+        .await
+        {
+            error!("MERGE into {} failed after retry queue replay: {}", job.final_table, e);
+        }
+    }
+    drop(client_guard);
+    heartbeat_task.abort();
+
+    match result {
+        Ok(next_token) => {
+            current_token.lock().await.insert(table_id, next_token);
+            sqlx::query("DELETE FROM snowflake_retry_queue WHERE id = $1")
+                .bind(id)
+                .execute(pool)
+                .await
+                .context("Failed to delete completed retry queue row")?;
+            info!("Replayed retry queue row {} for table {}", id, table_id);
+        }
+        Err(err) => {
+            let next_attempts = attempts + 1;
+            if next_attempts >= MAX_ATTEMPTS {
+                warn!(
+                    "Retry queue row {} for table {} exceeded {} attempts, parking as failed: {}",
+                    id, table_id, MAX_ATTEMPTS, err
+                );
+                sqlx::query("UPDATE snowflake_retry_queue SET status = 'failed', attempts = $2 WHERE id = $1")
+                    .bind(id)
+                    .bind(next_attempts)
+                    .execute(pool)
+                    .await
+                    .context("Failed to park retry queue row as failed")?;
+            } else {
+                let delay = backoff_secs(next_attempts);
+                warn!(
+                    "Retry queue row {} for table {} failed (attempt {}), retrying in {}s: {}",
+                    id, table_id, next_attempts, delay, err
+                );
+                sqlx::query(
+                    "UPDATE snowflake_retry_queue \
+                     SET status = 'new', attempts = $2, next_attempt_at = now() + ($3 || ' seconds')::interval \
+                     WHERE id = $1",
+                )
+                .bind(id)
+                .bind(next_attempts)
+                .bind(delay.to_string())
+                .execute(pool)
+                .await
+                .context("Failed to re-queue retry queue row")?;
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+/// Spawns the background worker that polls `snowflake_retry_queue` and
+/// drains it, backing off to `POLL_INTERVAL` between passes whenever there
+/// was nothing to claim.
+pub fn spawn_worker(
+    pool: Pool<Postgres>,
+    client_pool: Arc<ClientPool>,
+    current_token: Arc<Mutex<HashMap<TableId, String>>>,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            match process_one(&pool, &client_pool, &current_token).await {
+                Ok(true) => continue,
+                Ok(false) => tokio::time::sleep(POLL_INTERVAL).await,
+                Err(err) => {
+                    error!("Retry queue worker error: {}", err);
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_each_attempt_up_to_the_cap() {
+        assert_eq!(backoff_secs(0), BACKOFF_BASE_SECS);
+        assert_eq!(backoff_secs(1), BACKOFF_BASE_SECS * 2);
+        assert_eq!(backoff_secs(2), BACKOFF_BASE_SECS * 4);
+        assert_eq!(backoff_secs(3), BACKOFF_BASE_SECS * 8);
+    }
+
+    #[test]
+    fn backoff_saturates_at_the_configured_max() {
+        assert_eq!(backoff_secs(10), BACKOFF_MAX_SECS);
+        assert_eq!(backoff_secs(MAX_ATTEMPTS), BACKOFF_MAX_SECS);
+        assert_eq!(backoff_secs(1_000), BACKOFF_MAX_SECS);
+    }
+}
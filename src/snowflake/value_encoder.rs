@@ -0,0 +1,340 @@
+//! Configurable, lossless `Cell`/`ArrayCell` -> JSON conversion.
+//!
+//! The original `cell_to_json_value` dropped `Cell::Bytes` as the
+//! placeholder string `"<bytes len=N>"` and fell through several
+//! `ArrayCell` variants to `{:?}` debug formatting, neither of which
+//! round-trips into a typed Snowflake column. `ValueEncoder` makes the two
+//! genuinely lossy choices - how binary data is encoded, and whether
+//! `Numeric` is sent as a JSON number (compact, but float precision limits
+//! apply downstream) or a string (exact, the default) - operator
+//! configurable, and serializes every `ArrayCell` variant explicitly.
+
+use etl::types::{ArrayCell, Cell};
+use serde_json::{Value, json};
+
+/// How `Cell::Bytes`/`ArrayCell::Bytes` are encoded into the JSON string
+/// Snowpipe loads into a `BINARY` column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryEncoding {
+    Base64,
+    Hex,
+}
+
+/// Whether `Cell::Numeric`/`ArrayCell::Numeric` is emitted as a JSON number
+/// or a string. Strings preserve arbitrary precision; numbers are more
+/// convenient downstream but round through an `f64` in transit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumericEncoding {
+    Number,
+    String,
+}
+
+/// Operator-configurable strategy for converting a `Cell`/`ArrayCell` into
+/// the JSON value Snowpipe Streaming ingests.
+#[derive(Debug, Clone, Copy)]
+pub struct ValueEncoder {
+    pub binary: BinaryEncoding,
+    pub numeric: NumericEncoding,
+}
+
+impl Default for ValueEncoder {
+    fn default() -> Self {
+        Self {
+            binary: BinaryEncoding::Base64,
+            numeric: NumericEncoding::String,
+        }
+    }
+}
+
+impl ValueEncoder {
+    /// Reads `SNOWFLAKE_BINARY_ENCODING` (`base64` default, or `hex`) and
+    /// `SNOWFLAKE_NUMERIC_ENCODING` (`string` default, or `number`).
+    pub fn from_env() -> Self {
+        let binary = match std::env::var("SNOWFLAKE_BINARY_ENCODING").as_deref() {
+            Ok("hex") => BinaryEncoding::Hex,
+            _ => BinaryEncoding::Base64,
+        };
+        let numeric = match std::env::var("SNOWFLAKE_NUMERIC_ENCODING").as_deref() {
+            Ok("number") => NumericEncoding::Number,
+            _ => NumericEncoding::String,
+        };
+        Self { binary, numeric }
+    }
+
+    fn encode_bytes(&self, bytes: &[u8]) -> String {
+        match self.binary {
+            BinaryEncoding::Base64 => {
+                use base64::Engine;
+                base64::engine::general_purpose::STANDARD.encode(bytes)
+            }
+            BinaryEncoding::Hex => hex::encode(bytes),
+        }
+    }
+
+    fn encode_numeric(&self, numeric: &impl std::fmt::Display) -> Value {
+        match self.numeric {
+            NumericEncoding::String => json!(numeric.to_string()),
+            NumericEncoding::Number => serde_json::Number::from_f64(numeric.to_string().parse().unwrap_or(0.0))
+                .map(Value::Number)
+                .unwrap_or(Value::Null),
+        }
+    }
+
+    /// Converts a single `Cell` into its JSON representation.
+    pub fn cell_to_json_value(&self, cell: &Cell) -> Value {
+        match cell {
+            Cell::Null => Value::Null,
+            Cell::Bool(v) => json!(v),
+            Cell::String(v) => json!(v),
+            Cell::I16(v) => json!(v),
+            Cell::I32(v) => json!(v),
+            Cell::I64(v) => json!(v),
+            Cell::F32(v) => json!(v),
+            Cell::F64(v) => json!(v),
+            Cell::Bytes(v) => json!(self.encode_bytes(v)),
+            Cell::Json(v) => v.clone(),
+            Cell::Numeric(v) => self.encode_numeric(v),
+            Cell::Uuid(v) => json!(v.to_string()),
+            Cell::Array(v) => self.array_cell_to_json_value(v),
+            Cell::Date(v) => json!(v.to_string()),
+            Cell::Time(v) => json!(v.to_string()),
+            Cell::Timestamp(v) => json!(v.to_string()),
+            Cell::TimestampTz(v) => json!(v.to_rfc3339()),
+            _ => json!(format!("{:?}", cell)),
+        }
+    }
+
+    fn array_cell_to_json_value(&self, array: &ArrayCell) -> Value {
+        match array {
+            ArrayCell::Bool(list) => json!(list),
+            ArrayCell::I16(list) => json!(list),
+            ArrayCell::I32(list) => json!(list),
+            ArrayCell::I64(list) => json!(list),
+            ArrayCell::F32(list) => json!(list),
+            ArrayCell::F64(list) => json!(list),
+            ArrayCell::String(list) => json!(list),
+            ArrayCell::Bytes(list) => json!(
+                list.iter()
+                    .map(|opt| opt.as_ref().map(|b| self.encode_bytes(b)))
+                    .collect::<Vec<_>>()
+            ),
+            ArrayCell::Numeric(list) => json!(
+                list.iter()
+                    .map(|opt| opt.as_ref().map(|n| self.encode_numeric(n)))
+                    .collect::<Vec<_>>()
+            ),
+            ArrayCell::Json(list) => json!(list),
+            ArrayCell::Date(list) => json!(
+                list.iter()
+                    .map(|opt| opt.as_ref().map(|d| d.to_string()))
+                    .collect::<Vec<_>>()
+            ),
+            ArrayCell::Time(list) => json!(
+                list.iter()
+                    .map(|opt| opt.as_ref().map(|t| t.to_string()))
+                    .collect::<Vec<_>>()
+            ),
+            ArrayCell::Timestamp(list) => json!(
+                list.iter()
+                    .map(|opt| opt.as_ref().map(|t| t.to_string()))
+                    .collect::<Vec<_>>()
+            ),
+            ArrayCell::TimestampTz(list) => json!(
+                list.iter()
+                    .map(|opt| opt.as_ref().map(|t| t.to_rfc3339()))
+                    .collect::<Vec<_>>()
+            ),
+            ArrayCell::Uuid(list) => json!(
+                list.iter()
+                    .map(|opt| opt.as_ref().map(|u| u.to_string()))
+                    .collect::<Vec<_>>()
+            ),
+            _ => json!(format!("{:?}", array)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+    use uuid::Uuid;
+
+    fn encoder(binary: BinaryEncoding, numeric: NumericEncoding) -> ValueEncoder {
+        ValueEncoder { binary, numeric }
+    }
+
+    #[test]
+    fn bytes_round_trip_base64() {
+        let enc = encoder(BinaryEncoding::Base64, NumericEncoding::String);
+        let value = enc.cell_to_json_value(&Cell::Bytes(vec![0xDE, 0xAD, 0xBE, 0xEF]));
+        assert_eq!(value, json!("3q2+7w=="));
+    }
+
+    #[test]
+    fn bytes_round_trip_hex() {
+        let enc = encoder(BinaryEncoding::Hex, NumericEncoding::String);
+        let value = enc.cell_to_json_value(&Cell::Bytes(vec![0xDE, 0xAD, 0xBE, 0xEF]));
+        assert_eq!(value, json!("deadbeef"));
+    }
+
+    #[test]
+    fn bool_round_trip() {
+        let enc = ValueEncoder::default();
+        assert_eq!(enc.cell_to_json_value(&Cell::Bool(true)), json!(true));
+    }
+
+    #[test]
+    fn string_round_trip() {
+        let enc = ValueEncoder::default();
+        assert_eq!(enc.cell_to_json_value(&Cell::String("hello".to_string())), json!("hello"));
+    }
+
+    #[test]
+    fn i32_round_trip() {
+        let enc = ValueEncoder::default();
+        assert_eq!(enc.cell_to_json_value(&Cell::I32(42)), json!(42));
+    }
+
+    #[test]
+    fn array_bool_round_trip() {
+        let enc = ValueEncoder::default();
+        let value = enc.cell_to_json_value(&Cell::Array(ArrayCell::Bool(vec![Some(true), None, Some(false)])));
+        assert_eq!(value, json!([true, null, false]));
+    }
+
+    #[test]
+    fn array_bytes_round_trip() {
+        let enc = ValueEncoder::default();
+        let value = enc.cell_to_json_value(&Cell::Array(ArrayCell::Bytes(vec![Some(vec![1, 2, 3]), None])));
+        assert_eq!(value, json!(["AQID", null]));
+    }
+
+    #[test]
+    fn null_round_trip() {
+        let enc = ValueEncoder::default();
+        assert_eq!(enc.cell_to_json_value(&Cell::Null), Value::Null);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let enc = ValueEncoder::default();
+        let payload = json!({"a": 1, "b": [true, null]});
+        assert_eq!(enc.cell_to_json_value(&Cell::Json(payload.clone())), payload);
+    }
+
+    #[test]
+    fn uuid_round_trip() {
+        let enc = ValueEncoder::default();
+        let id = Uuid::parse_str("550e8400-e29b-41d4-a716-446655440000").unwrap();
+        let value = enc.cell_to_json_value(&Cell::Uuid(id));
+        assert_eq!(value, json!("550e8400-e29b-41d4-a716-446655440000"));
+    }
+
+    #[test]
+    fn date_round_trip() {
+        let enc = ValueEncoder::default();
+        let date = NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+        assert_eq!(enc.cell_to_json_value(&Cell::Date(date)), json!("2024-01-02"));
+    }
+
+    #[test]
+    fn time_round_trip() {
+        let enc = ValueEncoder::default();
+        let time = NaiveTime::from_hms_opt(3, 4, 5).unwrap();
+        assert_eq!(enc.cell_to_json_value(&Cell::Time(time)), json!("03:04:05"));
+    }
+
+    #[test]
+    fn timestamp_round_trip() {
+        let enc = ValueEncoder::default();
+        let ts = NaiveDate::from_ymd_opt(2024, 1, 2)
+            .unwrap()
+            .and_hms_opt(3, 4, 5)
+            .unwrap();
+        assert_eq!(enc.cell_to_json_value(&Cell::Timestamp(ts)), json!(ts.to_string()));
+    }
+
+    #[test]
+    fn timestamptz_round_trip() {
+        let enc = ValueEncoder::default();
+        let naive: NaiveDateTime = NaiveDate::from_ymd_opt(2024, 1, 2)
+            .unwrap()
+            .and_hms_opt(3, 4, 5)
+            .unwrap();
+        let ts: DateTime<Utc> = DateTime::from_naive_utc_and_offset(naive, Utc);
+        assert_eq!(enc.cell_to_json_value(&Cell::TimestampTz(ts)), json!(ts.to_rfc3339()));
+    }
+
+    // `Cell::Numeric`/`ArrayCell::Numeric` wrap a type from `etl::types` this
+    // tree doesn't include, so the two `NumericEncoding` tests below call
+    // `encode_numeric` directly with a string stand-in instead of going
+    // through `Cell::Numeric` (same gap noted in `super::merge`/`super::ddl`).
+
+    #[test]
+    fn numeric_string_encoding_is_a_json_string() {
+        let enc = encoder(BinaryEncoding::Base64, NumericEncoding::String);
+        let numeric = "12345.6789";
+        assert_eq!(enc.encode_numeric(&numeric), json!("12345.6789"));
+    }
+
+    #[test]
+    fn numeric_number_encoding_parses_to_a_json_number() {
+        let enc = encoder(BinaryEncoding::Base64, NumericEncoding::Number);
+        let numeric = "42.5";
+        assert_eq!(enc.encode_numeric(&numeric), json!(42.5));
+    }
+
+    #[test]
+    fn array_string_round_trip() {
+        let enc = ValueEncoder::default();
+        let value = enc.cell_to_json_value(&Cell::Array(ArrayCell::String(vec![
+            Some("a".to_string()),
+            None,
+        ])));
+        assert_eq!(value, json!(["a", null]));
+    }
+
+    #[test]
+    fn array_i64_round_trip() {
+        let enc = ValueEncoder::default();
+        let value = enc.cell_to_json_value(&Cell::Array(ArrayCell::I64(vec![Some(1), None, Some(3)])));
+        assert_eq!(value, json!([1, null, 3]));
+    }
+
+    #[test]
+    fn array_json_round_trip() {
+        let enc = ValueEncoder::default();
+        let list = vec![json!({"x": 1}), json!(null)];
+        let value = enc.cell_to_json_value(&Cell::Array(ArrayCell::Json(list.clone())));
+        assert_eq!(value, json!(list));
+    }
+
+    #[test]
+    fn array_uuid_round_trip() {
+        let enc = ValueEncoder::default();
+        let id = Uuid::parse_str("550e8400-e29b-41d4-a716-446655440000").unwrap();
+        let value = enc.cell_to_json_value(&Cell::Array(ArrayCell::Uuid(vec![Some(id), None])));
+        assert_eq!(value, json!(["550e8400-e29b-41d4-a716-446655440000", null]));
+    }
+
+    #[test]
+    fn array_date_round_trip() {
+        let enc = ValueEncoder::default();
+        let date = NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+        let value = enc.cell_to_json_value(&Cell::Array(ArrayCell::Date(vec![Some(date), None])));
+        assert_eq!(value, json!(["2024-01-02", null]));
+    }
+
+    #[test]
+    fn array_timestamptz_round_trip() {
+        let enc = ValueEncoder::default();
+        let naive: NaiveDateTime = NaiveDate::from_ymd_opt(2024, 1, 2)
+            .unwrap()
+            .and_hms_opt(3, 4, 5)
+            .unwrap();
+        let ts: DateTime<Utc> = DateTime::from_naive_utc_and_offset(naive, Utc);
+        let value = enc.cell_to_json_value(&Cell::Array(ArrayCell::TimestampTz(vec![Some(ts), None])));
+        assert_eq!(value, json!([ts.to_rfc3339(), null]));
+    }
+}